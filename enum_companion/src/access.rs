@@ -0,0 +1,72 @@
+use std::fmt;
+
+/// Who may read or write a field, given with `#[companion(read = ..., write = ...)]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    /// Anyone, the default.
+    Public,
+    /// The roles named here, e.g. `read = "admin"`.
+    Role(&'static str),
+    /// The roles with at least this level, e.g. `read = 2`.
+    Level(u32),
+}
+
+/// A role checked against the [`Access`] of fields by the generated `fields_readable_by`,
+/// `value_as` and `update_as` methods.
+///
+/// Implemented for `str`, which matches [`Access::Role`] by name, and for `u32`, which
+/// matches [`Access::Level`] when it is at least the level. Applications with a hierarchy
+/// of roles implement it for their own role type.
+pub trait Role {
+    /// Returns `true` if the role is granted `access`.
+    fn can(&self, access: Access) -> bool;
+}
+
+impl Role for str {
+    fn can(&self, access: Access) -> bool {
+        match access {
+            Access::Public => true,
+            Access::Role(name) => name == self,
+            Access::Level(_) => false,
+        }
+    }
+}
+
+impl Role for u32 {
+    fn can(&self, access: Access) -> bool {
+        match access {
+            Access::Public => true,
+            Access::Role(_) => false,
+            Access::Level(level) => *self >= level,
+        }
+    }
+}
+
+impl<R: Role + ?Sized> Role for &R {
+    fn can(&self, access: Access) -> bool {
+        (**self).can(access)
+    }
+}
+
+/// The error returned when a role isn't granted access to a field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessError {
+    /// The role may not read the field, named here.
+    Read(&'static str),
+    /// The role may not write the field, named here.
+    Write(&'static str),
+    /// The field, named here, is opaque and has no value to read.
+    Opaque(&'static str),
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessError::Read(name) => write!(f, "Field {} is not readable by this role", name),
+            AccessError::Write(name) => write!(f, "Field {} is not writable by this role", name),
+            AccessError::Opaque(name) => write!(f, "Field {} is opaque and has no value", name),
+        }
+    }
+}
+
+impl std::error::Error for AccessError {}
@@ -0,0 +1,294 @@
+use std::fmt;
+
+/// The error returned when decoding fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ended in the middle of a value.
+    UnexpectedEnd,
+    /// A varint is longer than 64 bits.
+    InvalidVarint,
+    /// A string is not valid UTF-8.
+    InvalidUtf8,
+    /// The bytes don't describe a value of the expected type.
+    InvalidValue(&'static str),
+    /// An encoded companion value has a field id unknown to this version of the struct.
+    ///
+    /// `len` is the size of the whole encoded value, which can be skipped to read the next one.
+    UnknownField { id: u64, len: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "Unexpected end of input"),
+            DecodeError::InvalidVarint => write!(f, "Invalid varint"),
+            DecodeError::InvalidUtf8 => write!(f, "Invalid UTF-8 string"),
+            DecodeError::InvalidValue(message) => write!(f, "Invalid value: {}", message),
+            DecodeError::UnknownField { id, .. } => write!(f, "Unknown field id: {}", id),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A type which can be written in the compact binary format.
+///
+/// Implemented for primitive types, `String`, `Option`, `Vec`, and for the structs deriving
+/// `EnumCompanion` and their value enums, when every field type implements it.
+pub trait Encode {
+    /// Appends the encoded value to `out`.
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+/// A type which can be read from the compact binary format.
+pub trait Decode: Sized {
+    /// Decodes a value from the start of `input`, and returns it with the number of bytes read.
+    fn decode(input: &[u8]) -> Result<(Self, usize), DecodeError>;
+}
+
+/// Appends `value` as an unsigned LEB128 varint.
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads an unsigned LEB128 varint, and returns it with the number of bytes read.
+pub fn read_varint(input: &[u8]) -> Result<(u64, usize), DecodeError> {
+    let mut value = 0u64;
+    for (i, &byte) in input.iter().enumerate() {
+        if i == 9 && byte > 1 {
+            return Err(DecodeError::InvalidVarint);
+        }
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(DecodeError::UnexpectedEnd)
+}
+
+/// Reads a varint length, and checks that `input` has that many bytes after it.
+fn read_len(input: &[u8]) -> Result<(usize, usize), DecodeError> {
+    let (len, read) = read_varint(input)?;
+    let len = usize::try_from(len).map_err(|_| DecodeError::UnexpectedEnd)?;
+    if input.len() - read < len {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    Ok((len, read))
+}
+
+/// Splits `N` bytes off the start of `input`.
+fn read_bytes<const N: usize>(input: &[u8]) -> Result<[u8; N], DecodeError> {
+    input
+        .get(..N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(DecodeError::UnexpectedEnd)
+}
+
+/// Unsigned integers are written as varints.
+macro_rules! unsigned {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    write_varint(out, *self as u64);
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(input: &[u8]) -> Result<(Self, usize), DecodeError> {
+                    let (value, read) = read_varint(input)?;
+                    let value = <$ty>::try_from(value)
+                        .map_err(|_| DecodeError::InvalidValue(concat!("out of range for ", stringify!($ty))))?;
+                    Ok((value, read))
+                }
+            }
+        )*
+    };
+}
+
+/// Signed integers are zigzag encoded, then written as varints.
+macro_rules! signed {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    let value = *self as i64;
+                    write_varint(out, ((value << 1) ^ (value >> 63)) as u64);
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(input: &[u8]) -> Result<(Self, usize), DecodeError> {
+                    let (value, read) = read_varint(input)?;
+                    let value = (value >> 1) as i64 ^ -((value & 1) as i64);
+                    let value = <$ty>::try_from(value)
+                        .map_err(|_| DecodeError::InvalidValue(concat!("out of range for ", stringify!($ty))))?;
+                    Ok((value, read))
+                }
+            }
+        )*
+    };
+}
+
+/// Floating point numbers and 128-bit integers are written as little-endian bytes.
+macro_rules! fixed {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(input: &[u8]) -> Result<(Self, usize), DecodeError> {
+                    let bytes = read_bytes(input)?;
+                    Ok((<$ty>::from_le_bytes(bytes), bytes.len()))
+                }
+            }
+        )*
+    };
+}
+
+unsigned!(u8, u16, u32, u64, usize);
+signed!(i8, i16, i32, i64, isize);
+fixed!(u128, i128, f32, f64);
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(u8::from(*self));
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &[u8]) -> Result<(Self, usize), DecodeError> {
+        match input.first() {
+            Some(0) => Ok((false, 1)),
+            Some(1) => Ok((true, 1)),
+            Some(_) => Err(DecodeError::InvalidValue("invalid bool")),
+            None => Err(DecodeError::UnexpectedEnd),
+        }
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(out, u64::from(*self));
+    }
+}
+
+impl Decode for char {
+    fn decode(input: &[u8]) -> Result<(Self, usize), DecodeError> {
+        let (value, read) = u32::decode(input)?;
+        let value = char::from_u32(value).ok_or(DecodeError::InvalidValue("invalid char"))?;
+        Ok((value, read))
+    }
+}
+
+/// Strings are written as their length in bytes, then their UTF-8 bytes.
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(out, self.len() as u64);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &[u8]) -> Result<(Self, usize), DecodeError> {
+        let (len, read) = read_len(input)?;
+        let value =
+            std::str::from_utf8(&input[read..read + len]).map_err(|_| DecodeError::InvalidUtf8)?;
+        Ok((value.to_string(), read + len))
+    }
+}
+
+/// Options are written as a 0 byte for `None`, or a 1 byte followed by the value.
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &[u8]) -> Result<(Self, usize), DecodeError> {
+        match input.first() {
+            Some(0) => Ok((None, 1)),
+            Some(1) => {
+                let (value, read) = T::decode(&input[1..])?;
+                Ok((Some(value), read + 1))
+            }
+            Some(_) => Err(DecodeError::InvalidValue("invalid option tag")),
+            None => Err(DecodeError::UnexpectedEnd),
+        }
+    }
+}
+
+/// Vectors are written as their number of elements, then the elements.
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_varint(out, self.len() as u64);
+        for element in self {
+            element.encode(out);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &[u8]) -> Result<(Self, usize), DecodeError> {
+        let (count, mut read) = read_varint(input)?;
+        // Each element takes at least one byte, which bounds the preallocation.
+        let mut elements = Vec::with_capacity((count as usize).min(input.len() - read));
+        for _ in 0..count {
+            let (element, len) = T::decode(&input[read..])?;
+            elements.push(element);
+            read += len;
+        }
+        Ok((elements, read))
+    }
+}
+
+/// Splits the encoded companion value at the start of `input` into its field id and payload,
+/// and returns them with the size of the whole encoded value.
+///
+/// Used by the generated `decode` methods.
+#[doc(hidden)]
+pub fn read_field(input: &[u8]) -> Result<(u64, &[u8], usize), DecodeError> {
+    let (id, id_len) = read_varint(input)?;
+    let (len, len_len) = read_len(&input[id_len..])?;
+    let start = id_len + len_len;
+    Ok((id, &input[start..start + len], start + len))
+}
+
+/// Appends an encoded companion value, made of its field id and its length-prefixed payload.
+///
+/// Used by the generated `encode` methods.
+#[doc(hidden)]
+pub fn write_field<T: Encode + ?Sized>(out: &mut Vec<u8>, id: u64, value: &T) {
+    let mut payload = Vec::new();
+    value.encode(&mut payload);
+    write_varint(out, id);
+    write_varint(out, payload.len() as u64);
+    out.extend_from_slice(&payload);
+}
+
+/// Decodes the payload of a companion value, which must be read entirely.
+///
+/// Used by the generated `decode` methods.
+#[doc(hidden)]
+pub fn decode_payload<T: Decode>(payload: &[u8]) -> Result<T, DecodeError> {
+    let (value, read) = T::decode(payload)?;
+    if read != payload.len() {
+        return Err(DecodeError::InvalidValue("trailing bytes in field payload"));
+    }
+    Ok(value)
+}
@@ -0,0 +1,55 @@
+use std::cell::RefCell;
+use std::fmt;
+
+/// A warning recorded when a deprecated field name is parsed by a generated `FromStr`.
+///
+/// Warnings are collected per thread, and returned by [`take_diagnostics`], so that the
+/// code loading a config file can report them once loading is done. At most
+/// [`MAX_DIAGNOSTICS`] are kept until they are taken; later ones are dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The name of the struct deriving `EnumCompanion`.
+    pub struct_name: &'static str,
+    /// The name of the deprecated field.
+    pub field: &'static str,
+    /// The name which was parsed, which may be an old name of the field.
+    pub used_name: String,
+    /// The deprecation note, given with `#[companion(deprecated = "...")]`.
+    pub note: &'static str,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Field {}.{} is deprecated: {}",
+            self.struct_name, self.field, self.note
+        )
+    }
+}
+
+/// The number of diagnostics kept per thread until [`take_diagnostics`] is called.
+pub const MAX_DIAGNOSTICS: usize = 256;
+
+thread_local! {
+    static DIAGNOSTICS: RefCell<Vec<Diagnostic>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Records a diagnostic for the current thread.
+///
+/// Used by the generated `FromStr` implementations.
+#[doc(hidden)]
+pub fn record_diagnostic(diagnostic: Diagnostic) {
+    DIAGNOSTICS.with(|diagnostics| {
+        let mut diagnostics = diagnostics.borrow_mut();
+        if diagnostics.len() < MAX_DIAGNOSTICS {
+            diagnostics.push(diagnostic);
+        }
+    });
+}
+
+/// Returns the diagnostics recorded on the current thread since the last call, in the order
+/// they were recorded.
+pub fn take_diagnostics() -> Vec<Diagnostic> {
+    DIAGNOSTICS.with(|diagnostics| diagnostics.take())
+}
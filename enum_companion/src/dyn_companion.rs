@@ -0,0 +1,125 @@
+use crate::{EnumCompanionField, Primitive, TypeInfo};
+use std::any::Any;
+
+/// An object-safe view of a struct deriving `EnumCompanion`, keyed by field name.
+///
+/// Unlike [`EnumCompanionTrait`](crate::EnumCompanionTrait), this trait has no type
+/// parameters and no associated functions, so structs of different types can be stored
+/// side by side in a `Vec<Box<dyn DynCompanion>>`. It is automatically implemented for
+/// structs that derive `EnumCompanion` and have no lifetime parameters.
+///
+/// Field names are resolved with the field enum's `from_name`, so the field name, the
+/// variant name and the old names are accepted. Unlike the field enum's `FromStr`, it records
+/// no [`Diagnostic`](crate::Diagnostic) for deprecated fields.
+pub trait DynCompanion {
+    /// Returns the name of the struct.
+    fn struct_name(&self) -> &'static str;
+
+    /// Returns the names of all fields, in field enum order.
+    fn field_names(&self) -> &'static [&'static str];
+
+    /// Returns the metadata of the field at `index` in [`field_names`](Self::field_names).
+    fn field_meta(&self, index: usize) -> Option<&'static dyn EnumCompanionField>;
+
+    /// Returns a reference to the value of a field.
+    fn get_any(&self, name: &str) -> Option<&dyn Any>;
+
+    /// Returns the value of a field formatted as a string.
+    ///
+    /// Returns `None` if the field does not exist or its type is not supported by
+    /// [`display_any`].
+    fn get_str(&self, name: &str) -> Option<String> {
+        self.get_any(name).and_then(display_any)
+    }
+
+    /// Sets the value of a field from a string, parsed according to the field's
+    /// [`TypeInfo`] with [`parse_any`].
+    fn set_str(&mut self, name: &str, value: &str) -> Result<(), String>;
+}
+
+/// Calls `$m!` with the list of `Primitive` variants and their Rust types.
+macro_rules! with_primitives {
+    ($m:ident, $($args:tt)*) => {
+        $m!(
+            $($args)*;
+            Bool => bool,
+            Char => char,
+            I8 => i8,
+            I16 => i16,
+            I32 => i32,
+            I64 => i64,
+            I128 => i128,
+            Isize => isize,
+            U8 => u8,
+            U16 => u16,
+            U32 => u32,
+            U64 => u64,
+            U128 => u128,
+            Usize => usize,
+            F32 => f32,
+            F64 => f64,
+            Str => String
+        )
+    };
+}
+
+/// Formats a type-erased value as a string.
+///
+/// Primitive types, `String`, `&'static str` and `Option`s of those are supported; `None`
+/// is formatted as an empty string. Returns `None` for any other type.
+pub fn display_any(value: &dyn Any) -> Option<String> {
+    macro_rules! display {
+        ($value:expr; $($variant:ident => $ty:ty),*) => {
+            $(
+                if let Some(value) = $value.downcast_ref::<$ty>() {
+                    return Some(value.to_string());
+                }
+                if let Some(value) = $value.downcast_ref::<Option<$ty>>() {
+                    return Some(value.as_ref().map(ToString::to_string).unwrap_or_default());
+                }
+            )*
+        };
+    }
+
+    with_primitives!(display, value);
+    if let Some(value) = value.downcast_ref::<&'static str>() {
+        return Some(value.to_string());
+    }
+    if let Some(value) = value.downcast_ref::<Option<&'static str>>() {
+        return Some(value.unwrap_or_default().to_string());
+    }
+    None
+}
+
+/// Parses a string into a boxed value of the type described by `info`.
+///
+/// Primitive types, `String` and `Option`s of those are supported; an empty string parses
+/// as `None`.
+pub fn parse_any(value: &str, info: &TypeInfo) -> Result<Box<dyn Any>, String> {
+    macro_rules! parse {
+        ($value:expr, $primitive:expr, $optional:expr; $($variant:ident => $ty:ty),*) => {
+            match $primitive {
+                $(
+                    Primitive::$variant => {
+                        if $optional && $value.is_empty() {
+                            return Ok(Box::new(None::<$ty>));
+                        }
+                        let parsed = $value.parse::<$ty>().map_err(|err| err.to_string())?;
+                        if $optional {
+                            Ok(Box::new(Some(parsed)))
+                        } else {
+                            Ok(Box::new(parsed))
+                        }
+                    }
+                )*
+            }
+        };
+    }
+
+    let (primitive, optional) = match info {
+        TypeInfo::Primitive(primitive) => (*primitive, false),
+        TypeInfo::Option(TypeInfo::Primitive(primitive)) => (*primitive, true),
+        _ => return Err("unsupported field type".to_string()),
+    };
+    with_primitives!(parse, value, primitive, optional)
+}
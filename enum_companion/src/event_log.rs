@@ -0,0 +1,189 @@
+use crate::{EnumCompanionField, EnumCompanionTrait};
+use std::time::SystemTime;
+
+/// A value recorded in an [`EventLog`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Event<V> {
+    /// The sequence number of the event, starting at 1 and increasing with each event.
+    pub seq: u64,
+    /// When the event was recorded, if the log records timestamps.
+    pub timestamp: Option<SystemTime>,
+    /// The value applied.
+    pub value: V,
+}
+
+/// An append-only log of the values applied to a struct deriving `EnumCompanion`.
+///
+/// Replaying the log on the initial state of the struct rebuilds its current state, which
+/// gives an audit trail and crash recovery without storing the struct itself. A snapshot
+/// replaces the log with one event per stored field of the current state, so replaying it
+/// doesn't need the history. Compacting the log keeps only the last write to each field,
+/// which shortens it while keeping the initial state it is replayed on.
+///
+/// With the `serde` feature, the log can be saved to and loaded from JSON Lines, one event
+/// per line, when the value enum implements `Serialize` and `Deserialize`.
+pub struct EventLog<S: EnumCompanionTrait> {
+    events: Vec<Event<S::Value>>,
+    next_seq: u64,
+    timestamps: bool,
+}
+
+impl<S: EnumCompanionTrait> Default for EventLog<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: EnumCompanionTrait> EventLog<S> {
+    /// Creates an empty log, without timestamps.
+    pub fn new() -> Self {
+        Self {
+            events: Vec::new(),
+            next_seq: 1,
+            timestamps: false,
+        }
+    }
+
+    /// Creates an empty log recording the time of each event.
+    pub fn with_timestamps() -> Self {
+        Self {
+            timestamps: true,
+            ..Self::new()
+        }
+    }
+
+    /// Returns the recorded events, in sequence order.
+    pub fn events(&self) -> &[Event<S::Value>] {
+        &self.events
+    }
+
+    /// Returns the number of recorded events.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Returns `true` if no event is recorded.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Records a value without applying it, and returns its sequence number.
+    pub fn record(&mut self, value: S::Value) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let timestamp = self.timestamps.then(SystemTime::now);
+        self.events.push(Event {
+            seq,
+            timestamp,
+            value,
+        });
+        seq
+    }
+
+    /// Applies a value to `target` and records it, and returns its sequence number.
+    pub fn apply(&mut self, target: &mut S, value: S::Value) -> u64
+    where
+        S::Value: Clone,
+    {
+        target.update(value.clone());
+        self.record(value)
+    }
+
+    /// Applies every recorded value to `initial`, in sequence order.
+    pub fn replay(&self, initial: S) -> S
+    where
+        S::Value: Clone,
+    {
+        self.replay_until(initial, u64::MAX)
+    }
+
+    /// Applies the recorded values up to sequence number `seq` included to `initial`, giving
+    /// the state of the struct at that point.
+    pub fn replay_until(&self, mut initial: S, seq: u64) -> S
+    where
+        S::Value: Clone,
+    {
+        for event in self.events.iter().take_while(|event| event.seq <= seq) {
+            initial.update(event.value.clone());
+        }
+        initial
+    }
+
+    /// Returns a log holding one event for each stored field of `state`, in field order.
+    ///
+    /// `state` is usually the result of [`replay`](Self::replay). Replaying the snapshot on
+    /// any initial state then rebuilds it, without the events which led to it. Virtual
+    /// fields are left out, since they are computed from the stored ones. The events of the
+    /// snapshot continue the sequence numbers of this log, and are timestamped if this log
+    /// records timestamps.
+    pub fn snapshot(&self, state: &S) -> Self {
+        let mut snapshot = Self {
+            events: Vec::new(),
+            next_seq: self.next_seq,
+            timestamps: self.timestamps,
+        };
+        for value in state.as_values() {
+            if !S::field_of(&value).is_virtual() {
+                snapshot.record(value);
+            }
+        }
+        snapshot
+    }
+
+    /// Removes every event overwritten by a later event for the same field.
+    ///
+    /// Replaying the compacted log gives the same state as before, as long as updates to one
+    /// field don't depend on the others, which is not the case for virtual fields with a
+    /// setter.
+    pub fn compact(&mut self) {
+        let mut seen = vec![false; S::fields().len()];
+        let mut events: Vec<_> = std::mem::take(&mut self.events)
+            .into_iter()
+            .rev()
+            .filter(|event| {
+                let index = S::field_of(&event.value).index();
+                !std::mem::replace(&mut seen[index], true)
+            })
+            .collect();
+        events.reverse();
+        self.events = events;
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<S: EnumCompanionTrait> EventLog<S> {
+    /// Writes the recorded events as JSON Lines.
+    pub fn write_jsonl<W: std::io::Write>(&self, mut writer: W) -> std::io::Result<()>
+    where
+        S::Value: serde::Serialize,
+    {
+        for event in &self.events {
+            serde_json::to_writer(&mut writer, event)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Reads a log written by [`write_jsonl`](Self::write_jsonl). Blank lines are ignored.
+    ///
+    /// New events continue the sequence numbers of the events read, and are timestamped if
+    /// any event read has a timestamp.
+    pub fn read_jsonl<R: std::io::BufRead>(reader: R) -> std::io::Result<Self>
+    where
+        S::Value: serde::de::DeserializeOwned,
+    {
+        let mut log = Self::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event: Event<S::Value> = serde_json::from_str(&line)?;
+            log.next_seq = log.next_seq.max(event.seq + 1);
+            log.timestamps |= event.timestamp.is_some();
+            log.events.push(event);
+        }
+        Ok(log)
+    }
+}
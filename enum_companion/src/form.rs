@@ -0,0 +1,237 @@
+//! HTML forms built from the metadata of structs deriving `EnumCompanion`.
+//!
+//! [`render_html`] renders a labelled input for each field, and [`apply_form`] applies the
+//! `application/x-www-form-urlencoded` body submitted by the form back to the struct.
+
+use crate::{DynCompanion, EnumCompanionField, Primitive, TypeInfo, parse_any};
+use std::fmt;
+
+/// The error reported for a field of a submitted form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormError {
+    /// The name of the field, as submitted.
+    pub field: String,
+    /// What is wrong with the submitted value.
+    pub message: String,
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for FormError {}
+
+/// The fields shown in a form: every field with a value which can be formatted as a string,
+/// except deprecated fields, sorted by their `order`.
+fn form_fields<S: DynCompanion + ?Sized>(
+    s: &S,
+) -> Vec<(&'static str, &'static dyn EnumCompanionField)> {
+    let mut fields: Vec<_> = s
+        .field_names()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, name)| Some((*name, s.field_meta(index)?)))
+        .filter(|(name, field)| field.deprecation().is_none() && s.get_str(name).is_some())
+        .collect();
+    fields.sort_by_key(|(_, field)| field.order());
+    fields
+}
+
+/// Returns `true` if the field is rendered as a checkbox.
+fn is_checkbox(field: &dyn EnumCompanionField) -> bool {
+    field.type_info() == &TypeInfo::Primitive(Primitive::Bool)
+}
+
+/// Returns the `type` attribute of the input for a field, and its `step` for floats.
+fn input_type(field: &dyn EnumCompanionField) -> (&'static str, Option<&'static str>) {
+    if field.is_sensitive() {
+        return ("password", None);
+    }
+    let primitive = match field.type_info() {
+        TypeInfo::Option(inner) => inner.primitive(),
+        info => info.primitive(),
+    };
+    match primitive {
+        Some(Primitive::Bool) if is_checkbox(field) => ("checkbox", None),
+        Some(primitive) if primitive.is_integer() => ("number", None),
+        Some(primitive) if primitive.is_float() => ("number", Some("any")),
+        _ => ("text", None),
+    }
+}
+
+/// Escapes text for use in HTML content and quoted attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders the fields of `s` as labelled HTML inputs, without the enclosing `<form>` tag.
+///
+/// Fields are sorted by their `order`, labelled with their `title`, and followed by their
+/// `description` if they have one. `bool` fields are checkboxes, numeric fields are number
+/// inputs, sensitive fields are empty password inputs, and other fields are text inputs.
+/// Read-only fields are rendered `readonly` (`disabled` for checkboxes). Deprecated fields
+/// and fields whose value can't be formatted as a string, see
+/// [`display_any`](crate::display_any), are left out.
+pub fn render_html<S: DynCompanion + ?Sized>(s: &S) -> String {
+    let mut html = String::new();
+    for (name, field) in form_fields(s) {
+        let id = escape(name);
+        let (kind, step) = input_type(field);
+        let mut attrs = format!(r#"type="{kind}" id="{id}" name="{id}""#);
+        if let Some(step) = step {
+            attrs.push_str(&format!(r#" step="{step}""#));
+        }
+        if kind == "checkbox" {
+            if s.get_str(name).as_deref() == Some("true") {
+                attrs.push_str(" checked");
+            }
+        } else if !field.is_sensitive() {
+            let value = s.get_str(name).unwrap_or_default();
+            attrs.push_str(&format!(r#" value="{}""#, escape(&value)));
+        }
+        if field.is_read_only() {
+            attrs.push_str(if kind == "checkbox" {
+                " disabled"
+            } else {
+                " readonly"
+            });
+        }
+
+        html.push_str("<div class=\"field\">\n");
+        html.push_str(&format!(
+            "  <label for=\"{id}\">{}</label>\n",
+            escape(field.title())
+        ));
+        html.push_str(&format!("  <input {attrs}>\n"));
+        if !field.description().is_empty() {
+            html.push_str(&format!(
+                "  <p class=\"description\">{}</p>\n",
+                escape(field.description())
+            ));
+        }
+        html.push_str("</div>\n");
+    }
+    html
+}
+
+/// Decodes a percent-encoded form component, where `+` stands for a space.
+fn decode_component(component: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(component.len());
+    let mut input = component.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [input.next(), input.next()];
+                let digits = match hex {
+                    [Some(high), Some(low)] => std::str::from_utf8(&[high, low])
+                        .ok()
+                        .and_then(|digits| u8::from_str_radix(digits, 16).ok()),
+                    _ => None,
+                };
+                bytes.push(digits.ok_or("invalid percent-encoding")?);
+            }
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| "invalid UTF-8".to_string())
+}
+
+/// Applies a form submitted as `application/x-www-form-urlencoded` to `s`.
+///
+/// Every value is decoded and parsed according to its field's [`TypeInfo`] first, and only
+/// when all of them are valid are they set with [`DynCompanion::set_str`]. Otherwise `s` is
+/// left unchanged, and the errors of all failing fields are returned together.
+///
+/// Browsers leave unchecked checkboxes out of the submitted form, so the checkbox fields
+/// rendered by [`render_html`] which are missing from `body` are set to `false`. Sensitive
+/// fields submitted empty keep their value, since their input is rendered empty. Read-only
+/// fields, which are rendered with their value, are submitted back unchanged and ignored.
+/// So are the keys which aren't fields, such as the name of the submit button or a CSRF
+/// token.
+pub fn apply_form<S: DynCompanion + ?Sized>(s: &mut S, body: &str) -> Result<(), Vec<FormError>> {
+    let mut errors = Vec::new();
+    let mut submitted = Vec::new();
+    let mut values: Vec<(String, String)> = Vec::new();
+    for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let name = match decode_component(name) {
+            Ok(name) => name,
+            Err(message) => {
+                errors.push(FormError {
+                    field: name.to_string(),
+                    message,
+                });
+                continue;
+            }
+        };
+        let field = s
+            .field_names()
+            .iter()
+            .position(|field_name| *field_name == name)
+            .and_then(|index| s.field_meta(index));
+        let result = decode_component(value).and_then(|value| match field {
+            None => Ok(None),
+            Some(field) if field.is_read_only() => Ok(None),
+            Some(field) if field.is_sensitive() && value.is_empty() => Ok(None),
+            Some(field) => {
+                let value = if is_checkbox(field) && value == "on" {
+                    "true".to_string()
+                } else {
+                    value
+                };
+                parse_any(&value, field.type_info())
+                    .map(|_| Some(value))
+                    .map_err(|err| format!("Invalid value for field {}: {}", name, err))
+            }
+        });
+        match result {
+            Ok(Some(value)) => values.push((name.clone(), value)),
+            Ok(None) => {}
+            Err(message) => errors.push(FormError {
+                field: name.clone(),
+                message,
+            }),
+        }
+        submitted.push(name);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    for (name, field) in form_fields(s) {
+        if is_checkbox(field)
+            && !field.is_read_only()
+            && !submitted.iter().any(|submitted| submitted == name)
+        {
+            values.push((name.to_string(), "false".to_string()));
+        }
+    }
+    for (name, value) in values {
+        if let Err(message) = s.set_str(&name, &value) {
+            errors.push(FormError {
+                field: name,
+                message,
+            });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
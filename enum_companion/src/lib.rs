@@ -1,25 +1,83 @@
 #![doc = include_str!("../README.md")]
 
 pub use enum_companion_derive::EnumCompanion;
+use std::any::Any;
+
+mod access;
+pub mod codec;
+pub mod diagnostics;
+mod dyn_companion;
+mod event_log;
+pub mod form;
+mod lww;
+mod merge;
+mod observable;
+mod shared;
+pub mod sql;
+mod transaction;
+mod type_info;
+pub use access::{Access, AccessError, Role};
+pub use codec::{Decode, DecodeError, Encode};
+pub use diagnostics::{Diagnostic, take_diagnostics};
+pub use dyn_companion::{DynCompanion, display_any, parse_any};
+pub use event_log::{Event, EventLog};
+pub use lww::{LwwRecord, Stamp};
+pub use merge::{Conflict, MergeResult, merge3};
+pub use observable::{Observable, Subscription};
+pub use shared::Shared;
+pub use transaction::{BatchError, Transaction};
+pub use type_info::{Primitive, SequenceKind, TypeInfo};
+
 /// A trait for accessing and updating struct fields dynamically.
 ///
-/// This trait is automatically implemented for structs that derive `EnumCompanion`
-/// and use the default method names.
-pub trait EnumCompanionTrait<F, V>
-where
-    F: Copy + 'static,
-{
+/// This trait is automatically implemented for every struct that derives `EnumCompanion`,
+/// whatever the names of the generated inherent methods.
+pub trait EnumCompanionTrait {
+    /// The generated field enum.
+    type Field: EnumCompanionField + Copy + 'static;
+
+    /// The generated value enum.
+    type Value: EnumCompanionValue<Field = Self::Field>;
+
     /// Returns the value of a specific field.
-    fn value(&self, field: F) -> V;
+    fn value(&self, field: Self::Field) -> Self::Value;
 
     /// Updates the value of a specific field.
-    fn update(&mut self, value: V);
+    fn update(&mut self, value: Self::Value);
 
     /// Returns an array of all field enum variants.
-    fn fields() -> &'static [F];
+    fn fields() -> &'static [Self::Field];
 
     /// Returns a vector of all field values.
-    fn as_values(&self) -> Vec<V>;
+    fn as_values(&self) -> Vec<Self::Value>;
+
+    /// Updates the value of a specific field, and returns a function restoring the field to
+    /// its state before the update.
+    ///
+    /// Stored fields are restored to their stored value as is, without `set_with`. Used by
+    /// [`Transaction`] to roll back.
+    #[doc(hidden)]
+    fn update_undoable<'s>(&mut self, value: Self::Value) -> Box<dyn FnOnce(&mut Self) + 's>
+    where
+        Self: 's;
+
+    /// Returns the field a value belongs to.
+    fn field_of(value: &Self::Value) -> Self::Field {
+        value.field()
+    }
+
+    /// Resolves a conflict found by [`merge3`], given the base, our and their values of a
+    /// field, according to the field's `merge` policy.
+    ///
+    /// Returns `None` for fields without a policy, whose conflicts are reported.
+    fn resolve_conflict(
+        base: &Self::Value,
+        ours: &Self::Value,
+        theirs: &Self::Value,
+    ) -> Option<Self::Value> {
+        let _ = (base, ours, theirs);
+        None
+    }
 }
 
 /// A trait for struct fields covered by the `EnumCompanion` derive macro, providing methods to access field data.
@@ -31,6 +89,12 @@ pub trait EnumCompanionField {
     /// Returns the type of the field as a string.
     fn type_str(&self) -> &'static str;
 
+    /// Returns a structured description of the field's type.
+    fn type_info(&self) -> &'static TypeInfo;
+
+    /// Returns the position of the field in the field enum's `FIELDS`.
+    fn index(&self) -> usize;
+
     /// Get a title for the field, typically used for display purposes.
     fn title(&self) -> &'static str {
         self.name()
@@ -43,18 +107,111 @@ pub trait EnumCompanionField {
     fn order(&self) -> u32 {
         0
     }
+
+    /// Returns `true` if the field is computed rather than stored in the struct.
+    fn is_virtual(&self) -> bool {
+        false
+    }
+
+    /// Returns `true` if updates to the field are ignored.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Returns `true` if the field has no variant in the value enum.
+    fn is_opaque(&self) -> bool {
+        false
+    }
+
+    /// Returns `true` if the field's value is redacted in `Debug` and string outputs.
+    fn is_sensitive(&self) -> bool {
+        false
+    }
+
+    /// Returns who may read the field's value.
+    fn read_access(&self) -> Access {
+        Access::Public
+    }
+
+    /// Returns who may write the field's value.
+    fn write_access(&self) -> Access {
+        Access::Public
+    }
+
+    /// Returns the name of the field's database column, the field name unless overridden.
+    fn column(&self) -> &'static str {
+        self.name()
+    }
+
+    /// Returns the previous names of the field, still accepted by the field enum's `FromStr`.
+    fn old_names(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Returns the deprecation note of the field, or `None` if it isn't deprecated.
+    fn deprecation(&self) -> Option<&'static str> {
+        None
+    }
 }
 
 /// A trait for struct values covered by the `EnumCompanion` derive macro, providing methods to access value data.
 /// This trait is automatically implemented for structs that derive `EnumCompanion`.
 pub trait EnumCompanionValue {
-    /// Returns the name of the field.
+    /// The generated field enum.
+    type Field: EnumCompanionField + Copy + 'static;
+
+    /// Returns the field this value belongs to.
+    fn field(&self) -> Self::Field;
+
+    /// Returns the name of the struct field, ignoring any `rename`.
+    ///
+    /// Use `self.field().name()` to get the name as returned by [`EnumCompanionField::name`].
     fn field_name(&self) -> &'static str;
 
     /// Returns the type of the field as a string.
     fn type_name(&self) -> &'static str;
 }
 
+/// Type-erased access to the fields of a struct, for code that only knows a field at runtime
+/// and never sees the concrete value enum.
+///
+/// This trait is object safe, so structs of different types can be edited through a single
+/// `&mut dyn EnumCompanionAny<F>`. It is automatically implemented for structs that derive
+/// `EnumCompanion` and have no lifetime parameters; generic type parameters must be `'static`.
+pub trait EnumCompanionAny<F> {
+    /// Returns a reference to the value of a specific field, or `None` for a virtual field
+    /// or a field with `get_with` or `set_with`.
+    fn field_any(&self, field: F) -> Option<&dyn Any>;
+
+    /// Returns a mutable reference to the value of a specific field, or `None` for a virtual
+    /// field or a field with `get_with` or `set_with`.
+    fn field_any_mut(&mut self, field: F) -> Option<&mut dyn Any>;
+
+    /// Sets the value of a specific field.
+    ///
+    /// Returns the value back if it does not have the field's type, or if the field is
+    /// read-only.
+    fn set_any(&mut self, field: F, value: Box<dyn Any>) -> Result<(), Box<dyn Any>>;
+}
+
+/// A visitor receiving each field of a struct deriving `EnumCompanion` with its concrete type,
+/// without cloning it into the value enum.
+///
+/// Pass it to the generated `visit` method. To require extra bounds on the field types, use
+/// `#[companion(visitor_bound = "...")]`: the derive then generates a `{StructName}Visitor`
+/// trait with the same shape, carrying those bounds, to be used instead of this one.
+pub trait FieldVisitor<F> {
+    /// Visits a single field.
+    fn field<T: ?Sized + 'static>(&mut self, field: F, value: &T);
+}
+
+/// A visitor receiving a mutable reference to each field, passed to the generated
+/// `visit_mut` method. See [`FieldVisitor`].
+pub trait FieldVisitorMut<F> {
+    /// Visits a single field.
+    fn field<T: ?Sized + 'static>(&mut self, field: F, value: &mut T);
+}
+
 extern crate self as enum_companion;
 
 // Tests
@@ -402,4 +559,1837 @@ mod tests {
         assert_eq!(distance_value.field_name(), "distance");
         assert_eq!(distance_value.type_name(), "u32");
     }
+
+    #[test]
+    fn test_type_info() {
+        use crate::{EnumCompanionField, Primitive, SequenceKind, TypeInfo};
+        use std::collections::HashMap;
+
+        #[allow(dead_code)]
+        #[derive(EnumCompanion)]
+        struct Test<'a, T: Clone> {
+            flag: bool,
+            count: Option<u16>,
+            tags: Vec<String>,
+            scores: HashMap<String, f64>,
+            pair: (i8, char),
+            bytes: [u8; 4],
+            label: &'a str,
+            data: T,
+            id: uuid::Uuid,
+        }
+
+        assert_eq!(
+            TestField::Flag.type_info(),
+            &TypeInfo::Primitive(Primitive::Bool)
+        );
+        assert_eq!(
+            TestField::Count.type_info(),
+            &TypeInfo::Option(&TypeInfo::Primitive(Primitive::U16))
+        );
+        assert!(TestField::Count.type_info().is_option());
+        assert_eq!(
+            TestField::Tags.type_info(),
+            &TypeInfo::Sequence {
+                kind: SequenceKind::Vec,
+                element: &TypeInfo::Primitive(Primitive::Str),
+            }
+        );
+        assert_eq!(
+            TestField::Scores.type_info(),
+            &TypeInfo::Map {
+                key: &TypeInfo::Primitive(Primitive::Str),
+                value: &TypeInfo::Primitive(Primitive::F64),
+            }
+        );
+        assert_eq!(
+            TestField::Pair.type_info(),
+            &TypeInfo::Tuple(&[
+                TypeInfo::Primitive(Primitive::I8),
+                TypeInfo::Primitive(Primitive::Char)
+            ])
+        );
+        assert_eq!(
+            TestField::Bytes.type_info(),
+            &TypeInfo::Sequence {
+                kind: SequenceKind::Array(4),
+                element: &TypeInfo::Primitive(Primitive::U8),
+            }
+        );
+        assert_eq!(
+            TestField::Label.type_info(),
+            &TypeInfo::Reference {
+                mutable: false,
+                inner: &TypeInfo::Primitive(Primitive::Str),
+            }
+        );
+        assert_eq!(TestField::Data.type_info(), &TypeInfo::Generic("T"));
+        assert_eq!(TestField::Id.type_info(), &TypeInfo::Opaque("uuid :: Uuid"));
+    }
+
+    #[test]
+    fn test_any() {
+        use crate::EnumCompanionAny;
+
+        #[derive(EnumCompanion)]
+        struct Test {
+            name: String,
+            distance: u32,
+        }
+
+        #[derive(EnumCompanion)]
+        struct TestGeneric<T: Clone> {
+            data: T,
+        }
+
+        let mut test = Test {
+            name: "Test".to_string(),
+            distance: 42,
+        };
+
+        assert_eq!(
+            test.field_any(TestField::Name)
+                .and_then(|value| value.downcast_ref::<String>()),
+            Some(&"Test".to_string())
+        );
+        *test
+            .field_any_mut(TestField::Distance)
+            .and_then(|value| value.downcast_mut::<u32>())
+            .unwrap() += 1;
+        assert_eq!(test.distance, 43);
+
+        assert!(test.set_any(TestField::Distance, Box::new(100u32)).is_ok());
+        assert_eq!(test.distance, 100);
+        let rejected = test.set_any(TestField::Distance, Box::new("wrong"));
+        assert_eq!(*rejected.unwrap_err().downcast::<&str>().unwrap(), "wrong");
+        assert_eq!(test.distance, 100);
+
+        // The trait is object safe.
+        let mut generic = TestGeneric { data: 1.5f64 };
+        let object: &mut dyn EnumCompanionAny<TestGenericField> = &mut generic;
+        object
+            .set_any(TestGenericField::Data, Box::new(2.5f64))
+            .unwrap();
+        assert_eq!(generic.data, 2.5);
+    }
+
+    #[test]
+    fn test_dyn_companion() {
+        use crate::DynCompanion;
+
+        #[derive(EnumCompanion)]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+
+        #[derive(EnumCompanion)]
+        struct Label {
+            #[companion(rename = "Text", title = "Label text")]
+            text: String,
+            size: Option<f32>,
+            id: uuid::Uuid,
+        }
+
+        let mut objects: Vec<Box<dyn DynCompanion>> = vec![
+            Box::new(Point { x: 1, y: 2 }),
+            Box::new(Label {
+                text: "hello".to_string(),
+                size: None,
+                id: uuid::Uuid::nil(),
+            }),
+        ];
+
+        assert_eq!(objects[0].struct_name(), "Point");
+        assert_eq!(objects[0].field_names(), &["x", "y"]);
+        assert_eq!(objects[1].field_names(), &["Text", "size", "id"]);
+        assert_eq!(objects[1].field_meta(0).unwrap().title(), "Label text");
+        assert!(objects[1].field_meta(3).is_none());
+
+        assert_eq!(objects[0].get_str("y"), Some("2".to_string()));
+        assert_eq!(objects[1].get_str("text"), Some("hello".to_string()));
+        assert_eq!(objects[1].get_str("size"), Some(String::new()));
+        assert_eq!(objects[1].get_str("id"), None);
+        assert_eq!(objects[1].get_str("missing"), None);
+        assert!(objects[1].get_any("id").unwrap().is::<uuid::Uuid>());
+
+        objects[0].set_str("x", "-5").unwrap();
+        objects[1].set_str("Text", "world").unwrap();
+        objects[1].set_str("size", "1.5").unwrap();
+        assert_eq!(objects[0].get_str("x"), Some("-5".to_string()));
+        assert_eq!(objects[1].get_str("Text"), Some("world".to_string()));
+        assert_eq!(objects[1].get_str("size"), Some("1.5".to_string()));
+        objects[1].set_str("size", "").unwrap();
+        assert_eq!(objects[1].get_str("size"), Some(String::new()));
+
+        assert!(objects[0].set_str("x", "abc").is_err());
+        assert!(objects[0].set_str("z", "1").is_err());
+        assert!(objects[1].set_str("id", "1").is_err());
+    }
+
+    #[test]
+    fn test_trait_associated_types() {
+        use crate::{EnumCompanionField, EnumCompanionTrait};
+
+        #[derive(EnumCompanion)]
+        #[companion(
+            value_fn = "get_field",
+            update_fn = "set_field",
+            fields_fn = "all_fields",
+            derive_field(PartialEq, Debug),
+            derive_value(Debug, PartialEq)
+        )]
+        struct Test {
+            name: String,
+            distance: u32,
+        }
+
+        fn field_names<S: EnumCompanionTrait>() -> Vec<&'static str> {
+            S::fields().iter().map(|field| field.name()).collect()
+        }
+
+        fn bump<S: EnumCompanionTrait>(s: &mut S, value: S::Value) -> S::Field {
+            let field = S::field_of(&value);
+            s.update(value);
+            field
+        }
+
+        let mut test = Test {
+            name: "Test".to_string(),
+            distance: 42,
+        };
+
+        // The trait is implemented even though the inherent methods are renamed.
+        assert_eq!(field_names::<Test>(), vec!["name", "distance"]);
+        assert_eq!(bump(&mut test, TestValue::Distance(7)), TestField::Distance);
+        assert_eq!(test.distance, 7);
+        assert_eq!(
+            EnumCompanionTrait::value(&test, TestField::Name),
+            TestValue::Name("Test".to_string())
+        );
+        assert_eq!(
+            EnumCompanionTrait::as_values(&test),
+            vec![TestValue::Name("Test".to_string()), TestValue::Distance(7)]
+        );
+    }
+
+    #[test]
+    fn test_value_field() {
+        use crate::{EnumCompanionField, EnumCompanionValue};
+        use std::str::FromStr;
+
+        #[derive(EnumCompanion)]
+        #[companion(derive_field(PartialEq, Debug))]
+        struct Test {
+            #[companion(rename = "Label")]
+            name: String,
+            distance: u32,
+        }
+
+        fn field_of<V: EnumCompanionValue>(value: &V) -> V::Field {
+            value.field()
+        }
+
+        let test = Test {
+            name: "Test".to_string(),
+            distance: 42,
+        };
+        for &field in Test::fields() {
+            assert_eq!(test.value(field).field(), field);
+        }
+        let label = test.value(TestField::Label);
+        assert_eq!(field_of(&label), TestField::Label);
+        assert_eq!(label.field().name(), "Label");
+        assert_eq!(label.field_name(), "name");
+        assert_eq!(
+            TestField::from_str(label.field().name()),
+            Ok(TestField::Label)
+        );
+    }
+
+    #[test]
+    fn test_into_values_and_take() {
+        #[derive(EnumCompanion)]
+        #[companion(derive_field(PartialEq, Debug), derive_value(Debug, PartialEq))]
+        struct Test {
+            name: String,
+            tags: Vec<String>,
+            #[allow(dead_code)]
+            #[companion(skip)]
+            cache: u8,
+        }
+
+        #[derive(EnumCompanion)]
+        struct NotDefault {
+            #[allow(dead_code)]
+            handle: std::sync::mpsc::Sender<()>,
+        }
+
+        let make = || Test {
+            name: "Test".to_string(),
+            tags: vec!["a".to_string()],
+            cache: 1,
+        };
+
+        let parts: [TestValue; TestField::COUNT] = make().into_parts();
+        assert_eq!(
+            parts,
+            [
+                TestValue::Name("Test".to_string()),
+                TestValue::Tags(vec!["a".to_string()])
+            ]
+        );
+        assert_eq!(make().into_values(), parts.to_vec());
+
+        let mut test = make();
+        assert_eq!(
+            test.take(TestField::Name),
+            TestValue::Name("Test".to_string())
+        );
+        assert_eq!(test.name, "");
+        assert_eq!(test.tags, vec!["a".to_string()]);
+
+        // `take` is unavailable, but the derive still compiles, when a field is not `Default`.
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        assert_eq!(NotDefault { handle: sender }.into_parts().len(), 1);
+    }
+
+    #[test]
+    fn test_values_array_and_iterators() {
+        #[derive(EnumCompanion)]
+        #[companion(derive_field(PartialEq, Debug), derive_value(Debug, PartialEq))]
+        struct Test<'a, T: Clone> {
+            name: &'a str,
+            data: T,
+        }
+
+        let test = Test {
+            name: "Test",
+            data: vec![1u8, 2],
+        };
+
+        assert_eq!(
+            test.values_array(),
+            [TestValue::Name("Test"), TestValue::Data(vec![1, 2])]
+        );
+        assert_eq!(test.iter_values().collect::<Vec<_>>(), test.as_values());
+
+        let refs: Vec<TestValueRef<'_, '_, Vec<u8>>> = test.iter_value_refs().collect();
+        assert!(test.value_ref(TestField::Data).is_some());
+        assert!(matches!(refs[0], TestValueRef::Name(&"Test")));
+        assert!(matches!(refs[1], TestValueRef::Data(data) if std::ptr::eq(data, &test.data)));
+        assert_eq!(refs[1].field(), TestField::Data);
+        assert_eq!(refs[1].to_value(), TestValue::Data(vec![1, 2]));
+        let copied = refs[0];
+        assert_eq!(copied.to_value(), test.value(TestField::Name));
+    }
+
+    #[test]
+    fn test_visitor() {
+        use crate::{EnumCompanionField, FieldVisitor, FieldVisitorMut};
+        use std::any::{TypeId, type_name};
+        use std::fmt::Debug;
+
+        #[derive(EnumCompanion)]
+        struct Test {
+            name: String,
+            distance: u32,
+        }
+
+        #[derive(EnumCompanion)]
+        #[companion(visitor_bound = "Debug + Default")]
+        struct Bounded {
+            name: String,
+            tags: Vec<u8>,
+        }
+
+        struct TypeNames(Vec<(&'static str, &'static str)>);
+        impl<F: EnumCompanionField> FieldVisitor<F> for TypeNames {
+            fn field<T: ?Sized + 'static>(&mut self, field: F, _value: &T) {
+                self.0.push((field.name(), type_name::<T>()));
+            }
+        }
+
+        struct NumericFields(Vec<&'static str>);
+        impl<F: EnumCompanionField> FieldVisitorMut<F> for NumericFields {
+            fn field<T: ?Sized + 'static>(&mut self, field: F, _value: &mut T) {
+                if TypeId::of::<T>() == TypeId::of::<u32>() {
+                    self.0.push(field.name());
+                }
+            }
+        }
+
+        struct Printer(Vec<String>);
+        impl BoundedVisitor for Printer {
+            fn field<T: ?Sized + 'static + Debug>(&mut self, field: BoundedField, value: &T) {
+                self.0.push(format!("{}={:?}", field.name(), value));
+            }
+        }
+
+        struct Reset;
+        impl BoundedVisitorMut for Reset {
+            fn field<T: 'static + Debug + Default>(&mut self, _field: BoundedField, value: &mut T) {
+                *value = T::default();
+            }
+        }
+
+        let mut test = Test {
+            name: "Test".to_string(),
+            distance: 21,
+        };
+        let mut names = TypeNames(Vec::new());
+        test.visit(&mut names);
+        assert_eq!(
+            names.0,
+            vec![("name", type_name::<String>()), ("distance", "u32")]
+        );
+        let mut numeric = NumericFields(Vec::new());
+        test.visit_mut(&mut numeric);
+        assert_eq!(numeric.0, vec!["distance"]);
+
+        let mut bounded = Bounded {
+            name: "b".to_string(),
+            tags: vec![1, 2],
+        };
+        let mut printer = Printer(Vec::new());
+        bounded.visit(&mut printer);
+        assert_eq!(printer.0, vec![r#"name="b""#, "tags=[1, 2]"]);
+        bounded.visit_mut(&mut Reset);
+        assert!(bounded.name.is_empty() && bounded.tags.is_empty());
+    }
+
+    #[test]
+    fn test_virtual_fields() {
+        use crate::{DynCompanion, EnumCompanionAny, EnumCompanionField, EnumCompanionTrait};
+
+        #[derive(EnumCompanion)]
+        #[companion(
+            derive_field(PartialEq, Debug),
+            derive_value(Debug, PartialEq),
+            virtual(
+                name = "full_name",
+                ty = String,
+                get = Self::full_name,
+                set = Self::set_full_name,
+                title = "Full name"
+            ),
+            virtual(name = "initials", ty = "String", get = "Self::initials")
+        )]
+        struct Person {
+            first: String,
+            last: String,
+        }
+
+        impl Person {
+            fn full_name(&self) -> String {
+                format!("{} {}", self.first, self.last)
+            }
+
+            fn set_full_name(&mut self, value: String) {
+                let (first, last) = value.split_once(' ').unwrap_or((&value, ""));
+                self.first = first.to_string();
+                self.last = last.to_string();
+            }
+
+            fn initials(&self) -> String {
+                [&self.first, &self.last]
+                    .iter()
+                    .filter_map(|part| part.chars().next())
+                    .collect()
+            }
+        }
+
+        let mut person = Person {
+            first: "Ada".to_string(),
+            last: "Lovelace".to_string(),
+        };
+
+        assert_eq!(
+            Person::fields(),
+            &[
+                PersonField::First,
+                PersonField::Last,
+                PersonField::FullName,
+                PersonField::Initials
+            ]
+        );
+        assert_eq!(PersonField::FullName.name(), "full_name");
+        assert_eq!(PersonField::FullName.title(), "Full name");
+        assert_eq!(PersonField::FullName.type_str(), "String");
+        assert!(PersonField::FullName.is_virtual());
+        assert!(!PersonField::FullName.is_read_only());
+        assert!(PersonField::Initials.is_read_only());
+        assert!(!PersonField::First.is_virtual());
+
+        assert_eq!(
+            person.value(PersonField::FullName),
+            PersonValue::FullName("Ada Lovelace".to_string())
+        );
+        person.update(PersonValue::FullName("Grace Hopper".to_string()));
+        assert_eq!(person.first, "Grace");
+        assert_eq!(person.last, "Hopper");
+
+        // Updates to a virtual field without a setter are ignored.
+        person.update(PersonValue::Initials("XX".to_string()));
+        assert_eq!(
+            person.value(PersonField::Initials),
+            PersonValue::Initials("GH".to_string())
+        );
+
+        assert_eq!(
+            person.values_array()[2],
+            PersonValue::FullName("Grace Hopper".to_string())
+        );
+        assert!(person.value_ref(PersonField::FullName).is_none());
+        assert_eq!(person.iter_value_refs().count(), 2);
+        assert!(person.field_any(PersonField::FullName).is_none());
+        assert_eq!(EnumCompanionTrait::as_values(&person).len(), 4);
+
+        assert_eq!(person.get_str("initials"), Some("GH".to_string()));
+        person.set_str("full_name", "Alan Turing").unwrap();
+        assert_eq!(person.first, "Alan");
+        assert!(person.set_str("initials", "AT").is_err());
+        assert!(
+            person
+                .set_any(PersonField::Initials, Box::new("AT".to_string()))
+                .is_err()
+        );
+
+        let parts = person.into_parts();
+        assert_eq!(parts[0], PersonValue::First("Alan".to_string()));
+        assert_eq!(parts[3], PersonValue::Initials("AT".to_string()));
+    }
+
+    #[test]
+    fn test_get_with_set_with() {
+        use crate::{DynCompanion, EnumCompanionAny, EnumCompanionField, Primitive, TypeInfo};
+        use std::sync::{Arc, Mutex};
+
+        fn cents_to_decimal(cents: &i64) -> f64 {
+            *cents as f64 / 100.0
+        }
+
+        fn decimal_to_cents(cents: &mut i64, value: f64) {
+            *cents = (value * 100.0).round() as i64;
+        }
+
+        fn set_lowercase(email: &mut String, value: String) {
+            *email = value.to_lowercase();
+        }
+
+        fn lock_get(shared: &Arc<Mutex<u32>>) -> u32 {
+            *shared.lock().unwrap()
+        }
+
+        fn lock_set(shared: &mut Arc<Mutex<u32>>, value: u32) {
+            *shared.lock().unwrap() = value;
+        }
+
+        #[derive(EnumCompanion, Default)]
+        #[companion(derive_field(PartialEq, Debug), derive_value(Debug, PartialEq))]
+        struct Account {
+            #[companion(
+                get_with = "cents_to_decimal",
+                set_with = "decimal_to_cents",
+                value_ty = "f64"
+            )]
+            balance: i64,
+            #[companion(set_with = "set_lowercase")]
+            email: String,
+            #[companion(get_with = "lock_get", set_with = "lock_set", value_ty = "u32")]
+            counter: Arc<Mutex<u32>>,
+        }
+
+        let counter = Arc::new(Mutex::new(7));
+        let mut account = Account {
+            balance: 1250,
+            email: "a@b.c".to_string(),
+            counter: counter.clone(),
+        };
+
+        assert_eq!(AccountField::Balance.type_str(), "f64");
+        assert_eq!(
+            AccountField::Counter.type_info(),
+            &TypeInfo::Primitive(Primitive::U32)
+        );
+        assert_eq!(
+            account.as_values(),
+            vec![
+                AccountValue::Balance(12.5),
+                AccountValue::Email("a@b.c".to_string()),
+                AccountValue::Counter(7),
+            ]
+        );
+
+        account.update(AccountValue::Balance(3.99));
+        account.update(AccountValue::Email("Jane@Example.COM".to_string()));
+        account.update(AccountValue::Counter(9));
+        assert_eq!(account.balance, 399);
+        assert_eq!(account.email, "jane@example.com");
+        assert_eq!(*counter.lock().unwrap(), 9);
+
+        assert!(account.value_ref(AccountField::Email).is_none());
+        assert!(account.field_any(AccountField::Balance).is_none());
+        account
+            .set_any(AccountField::Balance, Box::new(1.0_f64))
+            .unwrap();
+        assert_eq!(account.balance, 100);
+        account.set_str("email", "X@Y.Z").unwrap();
+        assert_eq!(account.get_str("email"), Some("x@y.z".to_string()));
+        assert_eq!(account.get_str("balance"), Some("1".to_string()));
+
+        // A rolled back transaction restores the stored values, bypassing `set_with`.
+        account.email = "Mixed@X".to_string();
+        {
+            let mut tx = account.begin();
+            tx.set(AccountValue::Email("New@Y".to_string())).unwrap();
+            tx.set(AccountValue::Balance(2.5)).unwrap();
+            assert_eq!(tx.email, "new@y");
+        }
+        assert_eq!(account.email, "Mixed@X");
+        assert_eq!(account.balance, 100);
+
+        assert_eq!(
+            account.take(AccountField::Balance),
+            AccountValue::Balance(1.0)
+        );
+        assert_eq!(account.balance, 0);
+
+        let parts = account.into_parts();
+        assert_eq!(parts[2], AccountValue::Counter(9));
+    }
+
+    #[test]
+    fn test_opaque_fields() {
+        use crate::{DynCompanion, EnumCompanionAny, EnumCompanionField, FieldVisitorMut};
+        use std::sync::Mutex;
+
+        #[derive(EnumCompanion)]
+        #[companion(derive_field(PartialEq, Debug), derive_value(Debug, PartialEq))]
+        struct Worker {
+            name: String,
+            #[companion(opaque, title = "Lock")]
+            lock: Mutex<u32>,
+            jobs: u32,
+        }
+
+        let mut worker = Worker {
+            name: "w".to_string(),
+            lock: Mutex::new(1),
+            jobs: 2,
+        };
+
+        assert_eq!(WorkerField::COUNT, 3);
+        assert_eq!(
+            Worker::fields(),
+            &[WorkerField::Name, WorkerField::Lock, WorkerField::Jobs]
+        );
+        assert_eq!("lock".parse::<WorkerField>(), Ok(WorkerField::Lock));
+        assert!(WorkerField::Lock.is_opaque());
+        assert!(!WorkerField::Jobs.is_opaque());
+        assert_eq!(WorkerField::Lock.title(), "Lock");
+        assert_eq!(WorkerField::Lock.type_str(), "Mutex < u32 >");
+
+        assert_eq!(worker.try_value(WorkerField::Lock), None);
+        assert_eq!(
+            worker.try_value(WorkerField::Jobs),
+            Some(WorkerValue::Jobs(2))
+        );
+        assert_eq!(
+            worker.as_values(),
+            vec![WorkerValue::Name("w".to_string()), WorkerValue::Jobs(2)]
+        );
+        assert_eq!(worker.values_array().len(), 2);
+        assert!(worker.value_ref(WorkerField::Lock).is_none());
+
+        let lock = worker.field_any(WorkerField::Lock).unwrap();
+        assert_eq!(
+            *lock.downcast_ref::<Mutex<u32>>().unwrap().lock().unwrap(),
+            1
+        );
+        worker
+            .set_any(WorkerField::Lock, Box::new(Mutex::new(5_u32)))
+            .unwrap();
+        assert_eq!(*worker.lock.lock().unwrap(), 5);
+        assert_eq!(worker.get_str("lock"), None);
+        assert_eq!(worker.get_str("jobs"), Some("2".to_string()));
+
+        struct Count(usize);
+        impl FieldVisitorMut<WorkerField> for Count {
+            fn field<T: ?Sized + 'static>(&mut self, _field: WorkerField, _value: &mut T) {
+                self.0 += 1;
+            }
+        }
+        let mut count = Count(0);
+        worker.visit_mut(&mut count);
+        assert_eq!(count.0, 3);
+
+        let parts = worker.into_parts();
+        assert_eq!(parts[1], WorkerValue::Jobs(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Field lock is opaque and has no value")]
+    fn test_opaque_field_value_panics() {
+        #[derive(EnumCompanion)]
+        struct Worker {
+            #[companion(opaque)]
+            lock: std::sync::Mutex<u32>,
+            jobs: u32,
+        }
+
+        let worker = Worker {
+            lock: std::sync::Mutex::new(1),
+            jobs: 2,
+        };
+        let _ = worker.jobs;
+        worker.value(WorkerField::Lock);
+    }
+
+    #[test]
+    fn test_observable() {
+        use crate::Observable;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        #[derive(EnumCompanion)]
+        #[companion(derive_field(PartialEq, Debug), derive_value(Debug, PartialEq))]
+        struct Settings {
+            volume: u8,
+            muted: bool,
+        }
+
+        let mut settings = Observable::new(Settings {
+            volume: 5,
+            muted: false,
+        });
+
+        let volume_events = Rc::new(RefCell::new(Vec::new()));
+        let all_events = Rc::new(RefCell::new(Vec::new()));
+
+        let events = volume_events.clone();
+        let volume_sub = settings.subscribe(SettingsField::Volume, move |field, old, new| {
+            events.borrow_mut().push((field, old.clone(), new.clone()));
+        });
+        let events = all_events.clone();
+        settings.subscribe_all(move |field, _, _| events.borrow_mut().push(field));
+
+        settings.update(SettingsValue::Volume(7));
+        settings.update(SettingsValue::Muted(true));
+        // Unchanged values don't notify.
+        settings.update(SettingsValue::Muted(true));
+
+        assert_eq!(
+            *volume_events.borrow(),
+            vec![(
+                SettingsField::Volume,
+                SettingsValue::Volume(5),
+                SettingsValue::Volume(7)
+            )]
+        );
+        assert_eq!(
+            *all_events.borrow(),
+            vec![SettingsField::Volume, SettingsField::Muted]
+        );
+
+        assert!(settings.unsubscribe(volume_sub));
+        assert!(!settings.unsubscribe(volume_sub));
+        settings.update(SettingsValue::Volume(9));
+        assert_eq!(volume_events.borrow().len(), 1);
+        assert_eq!(all_events.borrow().len(), 3);
+
+        assert_eq!(settings.volume, 9);
+        assert!(settings.into_inner().muted);
+
+        // The setter of a virtual field notifies the stored fields it changes, and a virtual
+        // field is notified when the stored fields it is computed from change.
+        #[derive(EnumCompanion)]
+        #[companion(
+            derive_field(PartialEq, Debug),
+            derive_value(Debug, PartialEq),
+            virtual(
+                name = "full_name",
+                ty = String,
+                get = Self::full_name,
+                set = Self::set_full_name
+            )
+        )]
+        struct Person {
+            first: String,
+            last: String,
+        }
+
+        impl Person {
+            fn full_name(&self) -> String {
+                format!("{} {}", self.first, self.last)
+            }
+
+            fn set_full_name(&mut self, value: String) {
+                let (first, last) = value.split_once(' ').unwrap_or((&value, ""));
+                self.first = first.to_string();
+                self.last = last.to_string();
+            }
+        }
+
+        let mut person = Observable::new(Person {
+            first: "Ada".to_string(),
+            last: "Byron".to_string(),
+        });
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let all = events.clone();
+        person.subscribe_all(move |field, _, _| all.borrow_mut().push(field));
+
+        person.update(PersonValue::FullName("Ada Lovelace".to_string()));
+        assert_eq!(
+            *events.borrow(),
+            vec![PersonField::Last, PersonField::FullName]
+        );
+        events.borrow_mut().clear();
+        person.update(PersonValue::First("Augusta".to_string()));
+        assert_eq!(
+            *events.borrow(),
+            vec![PersonField::First, PersonField::FullName]
+        );
+    }
+
+    #[test]
+    fn test_transaction() {
+        use crate::BatchError;
+
+        #[derive(EnumCompanion)]
+        #[companion(
+            derive_value(Debug, PartialEq),
+            virtual(name = "total", ty = "u32", get = "Self::total")
+        )]
+        struct Range {
+            min: u32,
+            max: u32,
+        }
+
+        impl Range {
+            fn total(&self) -> u32 {
+                self.min + self.max
+            }
+        }
+
+        let mut range = Range { min: 1, max: 5 };
+
+        range
+            .update_many([RangeValue::Min(2), RangeValue::Max(8)])
+            .unwrap();
+        assert_eq!((range.min, range.max), (2, 8));
+
+        // A read-only field rejects the whole batch.
+        assert_eq!(
+            range.update_many([RangeValue::Min(3), RangeValue::Total(0), RangeValue::Max(9)]),
+            Err(BatchError::ReadOnly("total"))
+        );
+        assert_eq!((range.min, range.max), (2, 8));
+
+        let validate = |range: &Range| {
+            if range.min <= range.max {
+                Ok(())
+            } else {
+                Err("min is greater than max".to_string())
+            }
+        };
+
+        let mut tx = range.begin().with_validator(validate);
+        tx.set(RangeValue::Min(4)).unwrap();
+        tx.set(RangeValue::Min(10)).unwrap();
+        assert_eq!(tx.min, 10);
+        assert_eq!(
+            tx.commit(),
+            Err(BatchError::Invalid("min is greater than max".to_string()))
+        );
+        assert_eq!(range.min, 2);
+
+        let mut tx = range.begin().with_validator(validate);
+        tx.set(RangeValue::Min(4)).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(range.min, 4);
+
+        // Dropping an uncommitted transaction restores the previous values.
+        {
+            let mut tx = range.begin();
+            tx.set(RangeValue::Max(0)).unwrap();
+        }
+        assert_eq!(range.max, 8);
+        let mut tx = range.begin();
+        tx.set(RangeValue::Max(0)).unwrap();
+        tx.rollback();
+        assert_eq!(range.max, 8);
+    }
+
+    #[test]
+    fn test_shared() {
+        use crate::{BatchError, EnumCompanionField, Shared};
+        use std::sync::Arc;
+        use std::thread;
+
+        #[derive(EnumCompanion, Clone)]
+        #[companion(
+            derive_field(Debug, PartialEq),
+            derive_value(Debug, PartialEq),
+            virtual(
+                name = "size",
+                ty = "(u32, u32)",
+                get = "Self::size",
+                set = "Self::set_size"
+            ),
+            virtual(name = "area", ty = "u32", get = "Self::area")
+        )]
+        struct Window {
+            width: u32,
+            height: u32,
+            title: String,
+        }
+
+        impl Window {
+            fn size(&self) -> (u32, u32) {
+                (self.width, self.height)
+            }
+
+            fn set_size(&mut self, (width, height): (u32, u32)) {
+                self.width = width;
+                self.height = height;
+            }
+
+            fn area(&self) -> u32 {
+                self.width * self.height
+            }
+        }
+
+        assert_eq!(WindowField::Title.index(), 2);
+        assert_eq!(WindowField::Area.index(), 4);
+
+        let shared = Arc::new(Shared::new(Window {
+            width: 1,
+            height: 1,
+            title: "main".to_string(),
+        }));
+
+        let handles: Vec<_> = (1..=4)
+            .map(|i| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        shared
+                            .update_many([WindowValue::Width(i), WindowValue::Height(i)])
+                            .unwrap();
+                        let snapshot = shared.snapshot();
+                        assert_eq!(snapshot.width, snapshot.height);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        shared.set(WindowValue::Title("other".to_string()));
+        assert_eq!(
+            shared.get(WindowField::Title),
+            WindowValue::Title("other".to_string())
+        );
+
+        shared.set(WindowValue::Size((3, 4)));
+        assert_eq!(shared.get(WindowField::Width), WindowValue::Width(3));
+        assert_eq!(shared.get(WindowField::Area), WindowValue::Area(12));
+        assert_eq!(
+            shared.update_many([WindowValue::Width(5), WindowValue::Area(1)]),
+            Err(BatchError::ReadOnly("area"))
+        );
+        assert_eq!(shared.snapshot().width, 3);
+    }
+
+    #[test]
+    fn test_event_log() {
+        use crate::EventLog;
+
+        #[derive(EnumCompanion, Clone, Default)]
+        #[companion(
+            derive_value(Debug, PartialEq, serde::Serialize, serde::Deserialize),
+            serde_value(tag = "field", content = "value")
+        )]
+        struct Settings {
+            theme: String,
+            font_size: u8,
+        }
+
+        let mut settings = Settings::default();
+        let mut log = EventLog::new();
+        assert_eq!(log.apply(&mut settings, SettingsValue::FontSize(12)), 1);
+        log.apply(&mut settings, SettingsValue::Theme("dark".to_string()));
+        log.apply(&mut settings, SettingsValue::FontSize(14));
+        assert_eq!(log.len(), 3);
+        assert!(log.events()[0].timestamp.is_none());
+
+        let replayed = log.replay(Settings::default());
+        assert_eq!(replayed.theme, "dark");
+        assert_eq!(replayed.font_size, 14);
+        assert_eq!(log.replay_until(Settings::default(), 2).font_size, 12);
+
+        log.compact();
+        let seqs: Vec<u64> = log.events().iter().map(|event| event.seq).collect();
+        assert_eq!(seqs, vec![2, 3]);
+        assert_eq!(log.replay(Settings::default()).font_size, 14);
+        assert_eq!(log.record(SettingsValue::FontSize(10)), 4);
+
+        // A snapshot rebuilds the state on any initial state, without the history.
+        let snapshot = log.snapshot(&log.replay(Settings::default()));
+        assert_eq!(
+            snapshot
+                .events()
+                .iter()
+                .map(|event| (event.seq, &event.value))
+                .collect::<Vec<_>>(),
+            vec![
+                (5, &SettingsValue::Theme("dark".to_string())),
+                (6, &SettingsValue::FontSize(10)),
+            ]
+        );
+        let initial = Settings {
+            theme: "light".to_string(),
+            font_size: 20,
+        };
+        let restored = snapshot.replay(initial);
+        assert_eq!((restored.theme.as_str(), restored.font_size), ("dark", 10));
+
+        let mut log = EventLog::<Settings>::with_timestamps();
+        log.record(SettingsValue::FontSize(1));
+        assert!(log.events()[0].timestamp.is_some());
+
+        #[cfg(feature = "serde")]
+        {
+            let mut log = EventLog::<Settings>::new();
+            log.record(SettingsValue::Theme("light".to_string()));
+            log.record(SettingsValue::FontSize(9));
+            let mut buffer = Vec::new();
+            log.write_jsonl(&mut buffer).unwrap();
+            let text = String::from_utf8(buffer.clone()).unwrap();
+            assert_eq!(text.lines().count(), 2);
+            assert!(text.starts_with(r#"{"seq":1,"timestamp":null,"value":{"field":"Theme""#));
+
+            let mut read = EventLog::<Settings>::read_jsonl(buffer.as_slice()).unwrap();
+            assert_eq!(read.events(), log.events());
+            assert_eq!(read.record(SettingsValue::FontSize(3)), 3);
+        }
+    }
+
+    #[test]
+    fn test_merge3() {
+        use crate::merge3;
+
+        fn max(_base: &u32, ours: &u32, theirs: &u32) -> u32 {
+            *ours.max(theirs)
+        }
+
+        #[derive(EnumCompanion, Clone, Debug, PartialEq)]
+        #[companion(derive_field(Debug, PartialEq), derive_value(Debug, PartialEq))]
+        struct Record {
+            title: String,
+            body: String,
+            #[companion(merge = "max")]
+            revision: u32,
+            #[companion(merge = "theirs")]
+            color: String,
+            #[companion(merge = "ours")]
+            owner: String,
+        }
+
+        let base = Record {
+            title: "title".to_string(),
+            body: "body".to_string(),
+            revision: 1,
+            color: "red".to_string(),
+            owner: "ann".to_string(),
+        };
+        let ours = Record {
+            title: "our title".to_string(),
+            body: "our body".to_string(),
+            revision: 3,
+            color: "blue".to_string(),
+            owner: "bob".to_string(),
+        };
+        let theirs = Record {
+            body: "their body".to_string(),
+            revision: 2,
+            color: "green".to_string(),
+            owner: "cid".to_string(),
+            ..base.clone()
+        };
+
+        let result = merge3(&base, &ours, &theirs);
+        assert!(!result.is_clean());
+        assert_eq!(result.conflicts.len(), 1);
+        let conflict = &result.conflicts[0];
+        assert_eq!(conflict.field, RecordField::Body);
+        assert_eq!(conflict.ours, RecordValue::Body("our body".to_string()));
+        assert_eq!(conflict.theirs, RecordValue::Body("their body".to_string()));
+        assert_eq!(
+            format!("{:?}", conflict),
+            r#"Conflict { field: Body, ours: Body("our body"), theirs: Body("their body") }"#
+        );
+        assert_eq!(result.clone(), result);
+        assert_eq!(
+            result.merged,
+            Record {
+                title: "our title".to_string(),
+                body: "our body".to_string(),
+                revision: 3,
+                color: "green".to_string(),
+                owner: "bob".to_string(),
+            }
+        );
+
+        let theirs = Record {
+            title: "their title".to_string(),
+            ..base.clone()
+        };
+        let ours = Record {
+            body: "our body".to_string(),
+            ..base.clone()
+        };
+        let result = merge3(&base, &ours, &theirs);
+        assert!(result.is_clean());
+        assert_eq!(result.merged.title, "their title");
+        assert_eq!(result.merged.body, "our body");
+    }
+
+    #[test]
+    fn test_lww_record() {
+        use crate::{LwwRecord, Stamp};
+
+        #[derive(EnumCompanion, Clone, Debug, PartialEq, Default)]
+        #[companion(derive_value(Debug, PartialEq))]
+        struct Profile {
+            name: String,
+            age: u32,
+        }
+
+        let mut a = LwwRecord::new(1, Profile::default());
+        let mut b = LwwRecord::new(2, Profile::default());
+        let mut c = LwwRecord::new(3, Profile::default());
+
+        assert_eq!(
+            a.set(ProfileValue::Name("ann".to_string())),
+            Some(Stamp {
+                clock: 1,
+                replica: 1
+            })
+        );
+        b.set(ProfileValue::Age(30));
+        b.set(ProfileValue::Name("bob".to_string()));
+        c.set(ProfileValue::Name("cid".to_string()));
+
+        // Concurrent writes with the same clock go to the greatest replica id.
+        let mut ab = LwwRecord::new(1, Profile::default());
+        ab.merge(&a);
+        ab.merge(&b);
+        let mut ba = LwwRecord::new(2, Profile::default());
+        ba.merge(&b);
+        ba.merge(&a);
+        assert_eq!(ab.to_struct(), ba.to_struct());
+        assert_eq!(
+            ab.to_struct(),
+            Profile {
+                name: "bob".to_string(),
+                age: 30
+            }
+        );
+
+        // Merging is idempotent and advances the clock.
+        a.merge(&b);
+        a.merge(&b);
+        assert_eq!(a.to_struct(), ab.to_struct());
+        assert_eq!(a.clock(), 2);
+        a.set(ProfileValue::Name("ann".to_string()));
+        assert_eq!(a.stamp(ProfileField::Name).unwrap().clock, 3);
+
+        // Deltas carry the writes since a given clock.
+        let delta = a.delta_since(2);
+        assert_eq!(delta.len(), 1);
+        c.apply_delta(b.delta_since(0));
+        c.apply_delta(delta);
+        assert_eq!(c.to_struct(), a.to_struct());
+        assert_eq!(
+            c.get(ProfileField::Name),
+            Some(&ProfileValue::Name("ann".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_codec() {
+        use crate::{Decode, DecodeError, Encode};
+
+        #[derive(EnumCompanion, Clone, Debug, PartialEq, Default)]
+        #[companion(derive_value(Debug, PartialEq))]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+
+        #[derive(EnumCompanion, Clone, Debug, PartialEq, Default)]
+        #[companion(
+            derive_value(Debug, PartialEq),
+            virtual(name = "len", ty = "usize", get = "Self::len")
+        )]
+        struct Shape {
+            name: String,
+            points: Vec<Point>,
+            closed: bool,
+            weight: Option<f64>,
+            tag: char,
+            big: u128,
+        }
+
+        impl Shape {
+            fn len(&self) -> usize {
+                self.points.len()
+            }
+        }
+
+        let mut out = Vec::new();
+        ShapeValue::Closed(true).encode(&mut out);
+        assert_eq!(out, vec![2, 1, 1]);
+        assert_eq!(ShapeValue::decode(&out), Ok((ShapeValue::Closed(true), 3)));
+
+        let shape = Shape {
+            name: "triangle".to_string(),
+            points: vec![
+                Point { x: 0, y: 0 },
+                Point { x: -300, y: 1 },
+                Point { x: 5, y: i32::MAX },
+            ],
+            closed: true,
+            weight: Some(1.5),
+            tag: 'é',
+            big: u128::MAX,
+        };
+        let mut out = Vec::new();
+        Encode::encode(&shape, &mut out);
+        let (decoded, read) = Shape::decode(&out).unwrap();
+        assert_eq!(decoded, shape);
+        assert_eq!(read, out.len());
+
+        let mut out = Vec::new();
+        for value in shape.as_values() {
+            value.encode(&mut out);
+        }
+        let mut read = 0;
+        let mut values = Vec::new();
+        while read < out.len() {
+            let (value, len) = ShapeValue::decode(&out[read..]).unwrap();
+            values.push(value);
+            read += len;
+        }
+        assert_eq!(values, shape.as_values());
+
+        // Values of unknown fields can be skipped.
+        let mut out = Vec::new();
+        crate::codec::write_varint(&mut out, 3);
+        crate::codec::write_field(&mut out, 0, &7_i32);
+        crate::codec::write_field(&mut out, 9, &"future".to_string());
+        crate::codec::write_field(&mut out, 1, &8_i32);
+        assert_eq!(
+            PointValue::decode(&out[4..]),
+            Err(DecodeError::UnknownField { id: 9, len: 9 })
+        );
+        assert_eq!(Point::decode(&out), Ok((Point { x: 7, y: 8 }, out.len())));
+
+        assert_eq!(
+            PointValue::decode(&[0, 5, 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+        assert_eq!(
+            bool::decode(&[2]),
+            Err(DecodeError::InvalidValue("invalid bool"))
+        );
+        assert_eq!(
+            u8::decode(&[0x80, 0x02]),
+            Err(DecodeError::InvalidValue("out of range for u8"))
+        );
+    }
+
+    #[test]
+    fn test_field_ids() {
+        use crate::{Decode, EnumCompanionField, codec::write_field};
+
+        #[derive(EnumCompanion, Debug, PartialEq, Default)]
+        #[companion(
+            derive_field(Debug, PartialEq),
+            derive_value(Debug, PartialEq),
+            field_repr = "u8",
+            virtual(name = "total", ty = "u32", get = "Self::total", id = 20)
+        )]
+        struct Order {
+            #[companion(id = 10)]
+            amount: u32,
+            #[companion(id = 3)]
+            fee: u32,
+        }
+
+        impl Order {
+            fn total(&self) -> u32 {
+                self.amount + self.fee
+            }
+        }
+
+        assert_eq!(std::mem::size_of::<OrderField>(), 1);
+        assert_eq!(OrderField::Amount.id(), 10u8);
+        assert_eq!(OrderField::Total.id(), 20);
+        assert_eq!(OrderField::Fee.index(), 1);
+        assert_eq!(OrderField::from_id(3), Some(OrderField::Fee));
+        assert_eq!(OrderField::from_id(4), None);
+        assert_eq!(OrderField::try_from(20u8), Ok(OrderField::Total));
+        assert_eq!(
+            OrderField::try_from(0u8),
+            Err("Invalid field id: 0".to_string())
+        );
+
+        // The binary encoding uses the ids.
+        let mut out = Vec::new();
+        OrderValue::Fee(1).encode(&mut out);
+        assert_eq!(out, [3, 1, 1]);
+        let mut out = vec![2];
+        write_field(&mut out, 3, &2u32);
+        write_field(&mut out, 10, &5u32);
+        assert_eq!(Order::decode(&out).unwrap().0, Order { amount: 5, fee: 2 });
+
+        #[derive(EnumCompanion)]
+        #[allow(dead_code)]
+        struct Plain {
+            a: u8,
+            b: u8,
+        }
+        assert_eq!(PlainField::B.id(), 1u16);
+        assert_eq!(PlainField::from_id(0).map(|f| f.index()), Some(0));
+    }
+
+    #[test]
+    fn test_old_names() {
+        use crate::EnumCompanionField;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static MIGRATED: AtomicUsize = AtomicUsize::new(0);
+
+        fn migrated(field: ServerField, name: &str) {
+            assert_eq!(field, ServerField::Host);
+            assert!(name == "hostname" || name == "host_name");
+            MIGRATED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        #[derive(EnumCompanion)]
+        #[companion(
+            derive_field(Debug, PartialEq, serde::Serialize, serde::Deserialize),
+            on_old_name = "migrated"
+        )]
+        #[allow(dead_code)]
+        struct Server {
+            #[companion(old_names("hostname", "host_name"))]
+            host: String,
+            port: u16,
+        }
+
+        assert_eq!("host".parse::<ServerField>(), Ok(ServerField::Host));
+        assert_eq!(MIGRATED.load(Ordering::SeqCst), 0);
+        assert_eq!("hostname".parse::<ServerField>(), Ok(ServerField::Host));
+        assert_eq!("host_name".parse::<ServerField>(), Ok(ServerField::Host));
+        assert_eq!(MIGRATED.load(Ordering::SeqCst), 2);
+        assert!("server".parse::<ServerField>().is_err());
+
+        assert_eq!(ServerField::Host.old_names(), &["hostname", "host_name"]);
+        assert!(ServerField::Port.old_names().is_empty());
+
+        let field: ServerField = serde_json::from_str("\"hostname\"").unwrap();
+        assert_eq!(field, ServerField::Host);
+        assert_eq!(serde_json::to_string(&field).unwrap(), "\"Host\"");
+    }
+
+    #[test]
+    fn test_deprecated_fields() {
+        use crate::{Diagnostic, DynCompanion, EnumCompanionField, take_diagnostics};
+
+        #[derive(EnumCompanion)]
+        #[companion(derive_field(Debug, PartialEq))]
+        #[allow(dead_code)]
+        struct Settings {
+            #[companion(deprecated = "use `timeout_ms` instead", old_names("wait"))]
+            timeout: u32,
+            timeout_ms: u64,
+        }
+
+        assert_eq!(
+            SettingsField::Timeout.deprecation(),
+            Some("use `timeout_ms` instead")
+        );
+        assert_eq!(SettingsField::TimeoutMs.deprecation(), None);
+        assert_eq!(SettingsField::DEPRECATED, &[SettingsField::Timeout]);
+        assert_eq!(Settings::fields_active(), &[SettingsField::TimeoutMs]);
+        assert_eq!(Settings::fields().len(), 2);
+
+        take_diagnostics();
+        assert_eq!("timeout_ms".parse(), Ok(SettingsField::TimeoutMs));
+        assert!(take_diagnostics().is_empty());
+
+        // Deprecated fields are still parsed, with a warning.
+        assert_eq!("timeout".parse(), Ok(SettingsField::Timeout));
+        assert_eq!("wait".parse(), Ok(SettingsField::Timeout));
+        let diagnostics = take_diagnostics();
+        assert_eq!(
+            diagnostics[0],
+            Diagnostic {
+                struct_name: "Settings",
+                field: "timeout",
+                used_name: "timeout".to_string(),
+                note: "use `timeout_ms` instead",
+            }
+        );
+        assert_eq!(diagnostics[1].used_name, "wait");
+        assert_eq!(
+            diagnostics[1].to_string(),
+            "Field Settings.timeout is deprecated: use `timeout_ms` instead"
+        );
+        assert!(take_diagnostics().is_empty());
+
+        // Internal lookups record nothing.
+        let settings = Settings {
+            timeout: 5,
+            timeout_ms: 5000,
+        };
+        assert_eq!(
+            SettingsField::from_name("wait"),
+            Some(SettingsField::Timeout)
+        );
+        assert_eq!(settings.get_str("timeout").as_deref(), Some("5"));
+        assert_eq!(settings.get_str("wait").as_deref(), Some("5"));
+        assert!(take_diagnostics().is_empty());
+
+        // Diagnostics which are never taken are capped.
+        for _ in 0..crate::diagnostics::MAX_DIAGNOSTICS + 10 {
+            let _ = "timeout".parse::<SettingsField>();
+        }
+        assert_eq!(
+            take_diagnostics().len(),
+            crate::diagnostics::MAX_DIAGNOSTICS
+        );
+    }
+
+    #[test]
+    fn test_sensitive_fields() {
+        use crate::{DynCompanion, EnumCompanionField};
+
+        #[derive(EnumCompanion)]
+        #[companion(derive_field(Debug, PartialEq), derive_value(Debug, PartialEq))]
+        struct Account {
+            username: String,
+            #[companion(sensitive)]
+            password_hash: String,
+        }
+
+        let mut account = Account {
+            username: "alice".to_string(),
+            password_hash: "secret".to_string(),
+        };
+        assert!(AccountField::PasswordHash.is_sensitive());
+        assert!(!AccountField::Username.is_sensitive());
+
+        assert_eq!(
+            format!("{:?}", account.as_values()),
+            r#"[Username("alice"), PasswordHash(***)]"#
+        );
+        assert_eq!(
+            account.as_values_redacted(),
+            vec![AccountValue::Username("alice".to_string())]
+        );
+        assert_eq!(account.get_str("password_hash").as_deref(), Some("***"));
+
+        // Sensitive fields can still be read and updated through the companion.
+        account.update(AccountValue::PasswordHash("changed".to_string()));
+        assert_eq!(account.password_hash, "changed");
+        assert_eq!(
+            account.value(AccountField::PasswordHash),
+            AccountValue::PasswordHash("changed".to_string())
+        );
+
+        // A sensitive field doesn't need a `Debug` type.
+        #[derive(Clone)]
+        struct Secret;
+
+        #[derive(EnumCompanion)]
+        #[companion(derive_value(Debug))]
+        #[allow(dead_code)]
+        struct Token<T: Clone, K: Clone> {
+            key: K,
+            #[companion(sensitive)]
+            secret: T,
+        }
+
+        assert_eq!(
+            format!("{:?}", TokenValue::<Secret, u8>::Secret(Secret)),
+            "Secret(***)"
+        );
+        assert_eq!(format!("{:?}", TokenValue::<Secret, u8>::Key(1)), "Key(1)");
+    }
+
+    #[test]
+    fn test_access() {
+        use crate::{Access, AccessError, EnumCompanionField, Role};
+
+        #[derive(EnumCompanion)]
+        #[companion(derive_field(Debug, PartialEq), derive_value(Debug, PartialEq))]
+        struct Tenant {
+            name: String,
+            #[companion(read = "admin", write = "owner")]
+            plan: String,
+            #[companion(write = 2)]
+            quota: u32,
+            #[companion(opaque)]
+            cache: Vec<String>,
+        }
+
+        let mut tenant = Tenant {
+            name: "acme".to_string(),
+            plan: "free".to_string(),
+            quota: 10,
+            cache: Vec::new(),
+        };
+        assert_eq!(TenantField::Plan.read_access(), Access::Role("admin"));
+        assert_eq!(TenantField::Quota.write_access(), Access::Level(2));
+        assert_eq!(TenantField::Name.read_access(), Access::Public);
+
+        assert_eq!(
+            Tenant::fields_readable_by("viewer"),
+            vec![TenantField::Name, TenantField::Quota]
+        );
+        assert_eq!(Tenant::fields_readable_by("admin").len(), 3);
+        for field in Tenant::fields_readable_by("admin") {
+            assert!(tenant.value_as("admin", field).is_ok());
+        }
+        assert_eq!(
+            tenant.value_as("admin", TenantField::Cache),
+            Err(AccessError::Opaque("cache"))
+        );
+        assert_eq!(
+            tenant.value_as("viewer", TenantField::Plan),
+            Err(AccessError::Read("plan"))
+        );
+        assert_eq!(
+            tenant.value_as("admin", TenantField::Plan),
+            Ok(TenantValue::Plan("free".to_string()))
+        );
+        assert_eq!(
+            tenant.update_as("admin", TenantValue::Plan("pro".to_string())),
+            Err(AccessError::Write("plan"))
+        );
+        assert_eq!(tenant.plan, "free");
+        assert_eq!(
+            tenant.update_as(&1, TenantValue::Quota(20)),
+            Err(AccessError::Write("quota"))
+        );
+        tenant.update_as(&2, TenantValue::Quota(20)).unwrap();
+        assert_eq!(tenant.quota, 20);
+
+        // A role hierarchy, where owners can do anything admins can.
+        #[derive(PartialEq, PartialOrd)]
+        enum Member {
+            Admin,
+            Owner,
+        }
+
+        impl Role for Member {
+            fn can(&self, access: Access) -> bool {
+                match access {
+                    Access::Public => true,
+                    Access::Role("admin") => *self >= Member::Admin,
+                    Access::Role("owner") => *self >= Member::Owner,
+                    _ => false,
+                }
+            }
+        }
+
+        assert!(tenant.value_as(&Member::Owner, TenantField::Plan).is_ok());
+        tenant
+            .update_as(&Member::Owner, TenantValue::Plan("pro".to_string()))
+            .unwrap();
+        assert_eq!(tenant.plan, "pro");
+        assert!(
+            tenant
+                .update_as(&Member::Admin, TenantValue::Plan("free".to_string()))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_form() {
+        use crate::form::{FormError, apply_form, render_html};
+
+        #[derive(EnumCompanion)]
+        #[companion(virtual(name = "summary", ty = "String", get = "Self::summary"))]
+        struct Server {
+            #[companion(title = "Host name", description = "Where to <connect>", order = 1)]
+            host: String,
+            #[companion(order = 2)]
+            port: u16,
+            #[companion(order = 3)]
+            ratio: Option<f64>,
+            #[companion(order = 4)]
+            enabled: bool,
+            #[companion(order = 5, sensitive)]
+            password: String,
+            #[companion(deprecated = "use `host`")]
+            address: String,
+            #[companion(opaque)]
+            tags: Vec<String>,
+        }
+
+        impl Server {
+            fn summary(&self) -> String {
+                format!("{}:{}", self.host, self.port)
+            }
+        }
+
+        let mut server = Server {
+            host: "a&b".to_string(),
+            port: 80,
+            ratio: None,
+            enabled: true,
+            password: "secret".to_string(),
+            address: String::new(),
+            tags: Vec::new(),
+        };
+
+        assert_eq!(
+            render_html(&server),
+            concat!(
+                "<div class=\"field\">\n",
+                "  <label for=\"summary\">summary</label>\n",
+                "  <input type=\"text\" id=\"summary\" name=\"summary\" value=\"a&amp;b:80\" readonly>\n",
+                "</div>\n",
+                "<div class=\"field\">\n",
+                "  <label for=\"host\">Host name</label>\n",
+                "  <input type=\"text\" id=\"host\" name=\"host\" value=\"a&amp;b\">\n",
+                "  <p class=\"description\">Where to &lt;connect&gt;</p>\n",
+                "</div>\n",
+                "<div class=\"field\">\n",
+                "  <label for=\"port\">port</label>\n",
+                "  <input type=\"number\" id=\"port\" name=\"port\" value=\"80\">\n",
+                "</div>\n",
+                "<div class=\"field\">\n",
+                "  <label for=\"ratio\">ratio</label>\n",
+                "  <input type=\"number\" id=\"ratio\" name=\"ratio\" step=\"any\" value=\"\">\n",
+                "</div>\n",
+                "<div class=\"field\">\n",
+                "  <label for=\"enabled\">enabled</label>\n",
+                "  <input type=\"checkbox\" id=\"enabled\" name=\"enabled\" checked>\n",
+                "</div>\n",
+                "<div class=\"field\">\n",
+                "  <label for=\"password\">password</label>\n",
+                "  <input type=\"password\" id=\"password\" name=\"password\">\n",
+                "</div>\n",
+            )
+        );
+
+        // Submitting the rendered form unchanged, read-only field included, changes nothing.
+        apply_form(
+            &mut server,
+            "summary=a%26b%3A80&host=a%26b&port=80&ratio=&enabled=on&password=",
+        )
+        .unwrap();
+        assert_eq!(server.host, "a&b");
+        assert_eq!(server.port, 80);
+        assert_eq!(server.ratio, None);
+        assert!(server.enabled);
+        assert_eq!(server.password, "secret");
+
+        // The unchecked checkbox is left out, and the empty password keeps its value. Keys
+        // which aren't fields, like the submit button, are ignored.
+        apply_form(
+            &mut server,
+            "host=example.com%2Fx&port=8080&ratio=0.5&password=&submit=Save",
+        )
+        .unwrap();
+        assert_eq!(server.host, "example.com/x");
+        assert_eq!(server.port, 8080);
+        assert_eq!(server.ratio, Some(0.5));
+        assert!(!server.enabled);
+        assert_eq!(server.password, "secret");
+
+        apply_form(&mut server, "enabled=on&password=hunter+2&address=old").unwrap();
+        assert!(server.enabled);
+        assert_eq!(server.password, "hunter 2");
+        assert_eq!(server.address, "old");
+
+        let errors =
+            apply_form(&mut server, "port=http&host=h&summary=x&nope=1&ratio=%zz").unwrap_err();
+        // Nothing is applied, not even the valid host or the unchecked checkbox.
+        assert_eq!(server.host, "example.com/x");
+        assert_eq!(server.port, 8080);
+        assert!(server.enabled);
+        let fields: Vec<_> = errors.iter().map(|error| error.field.as_str()).collect();
+        assert_eq!(fields, ["port", "ratio"]);
+        assert_eq!(
+            errors[1],
+            FormError {
+                field: "ratio".to_string(),
+                message: "invalid percent-encoding".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_sql() {
+        use crate::EnumCompanionField;
+        use crate::sql::{Dialect, Statement, Table};
+
+        #[derive(EnumCompanion)]
+        #[companion(
+            derive_value(Debug, PartialEq),
+            virtual(name = "label", ty = "String", get = "Self::label")
+        )]
+        struct User {
+            id: i64,
+            #[companion(column = "user_name")]
+            name: String,
+            age: Option<u8>,
+            score: f64,
+            avatar: Vec<u8>,
+            #[companion(opaque)]
+            cache: std::collections::HashMap<String, String>,
+        }
+
+        impl User {
+            fn label(&self) -> String {
+                format!("#{} {}", self.id, self.name)
+            }
+        }
+
+        let user = User {
+            id: 7,
+            name: "alice".to_string(),
+            age: None,
+            score: 1.5,
+            avatar: vec![1, 2],
+            cache: Default::default(),
+        };
+        assert_eq!(UserField::Name.column(), "user_name");
+        assert_eq!(UserField::Age.column(), "age");
+
+        let table = Table::<User>::new("users", UserField::Id).unwrap();
+        assert_eq!(
+            table.columns(),
+            ["id", "user_name", "age", "score", "avatar"]
+        );
+        assert_eq!(
+            table.create_table(),
+            r#"CREATE TABLE "users" ("id" INTEGER PRIMARY KEY, "user_name" TEXT NOT NULL, "age" INTEGER, "score" REAL NOT NULL, "avatar" BLOB NOT NULL)"#
+        );
+        assert_eq!(
+            table.select(),
+            r#"SELECT "id", "user_name", "age", "score", "avatar" FROM "users""#
+        );
+        assert_eq!(
+            table.insert(&user),
+            Statement {
+                sql: r#"INSERT INTO "users" ("id", "user_name", "age", "score", "avatar") VALUES (?, ?, ?, ?, ?)"#.to_string(),
+                params: vec![
+                    UserValue::Id(7),
+                    UserValue::Name("alice".to_string()),
+                    UserValue::Age(None),
+                    UserValue::Score(1.5),
+                    UserValue::Avatar(vec![1, 2]),
+                ],
+            }
+        );
+
+        // Virtual and opaque fields, the key and repeated fields are left out.
+        let update = table
+            .update(
+                &user,
+                &[
+                    UserField::Score,
+                    UserField::Label,
+                    UserField::Id,
+                    UserField::Name,
+                    UserField::Score,
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            update.sql,
+            r#"UPDATE "users" SET "score" = ?, "user_name" = ? WHERE "id" = ?"#
+        );
+        assert_eq!(
+            update.params,
+            [
+                UserValue::Score(1.5),
+                UserValue::Name("alice".to_string()),
+                UserValue::Id(7)
+            ]
+        );
+        assert!(table.update(&user, &[UserField::Cache]).is_none());
+
+        let table = Table::<User>::new("users", UserField::Id)
+            .unwrap()
+            .with_dialect(Dialect::Postgres);
+        assert_eq!(
+            table.create_table(),
+            r#"CREATE TABLE "users" ("id" BIGINT PRIMARY KEY, "user_name" TEXT NOT NULL, "age" SMALLINT, "score" DOUBLE PRECISION NOT NULL, "avatar" BYTEA NOT NULL)"#
+        );
+        assert_eq!(
+            table.update(&user, &[UserField::Age]).unwrap().sql,
+            r#"UPDATE "users" SET "age" = $1 WHERE "id" = $2"#
+        );
+    }
+
+    #[test]
+    fn test_only_opaque_fields() {
+        use crate::EnumCompanionField;
+
+        #[derive(EnumCompanion)]
+        #[companion(derive_field(Debug, PartialEq), derive_value(Debug, PartialEq))]
+        struct Handle {
+            #[companion(opaque)]
+            file: Option<std::fs::File>,
+        }
+
+        #[derive(EnumCompanion)]
+        #[allow(dead_code)]
+        struct Borrowed<'a, T> {
+            #[companion(opaque)]
+            inner: &'a T,
+        }
+
+        let handle = Handle { file: None };
+        assert!(HandleField::File.is_opaque());
+        assert!(handle.as_values().is_empty());
+        assert_eq!(handle.try_value(HandleField::File), None);
+        assert_eq!(Borrowed::<u8>::fields().len(), 1);
+    }
+
+    #[test]
+    fn test_sql_invalid_key() {
+        use crate::sql::{SqlError, Table};
+
+        #[derive(EnumCompanion)]
+        #[companion(virtual(name = "label", ty = "String", get = "Self::label"))]
+        struct User {
+            id: i64,
+        }
+
+        impl User {
+            fn label(&self) -> String {
+                format!("#{}", self.id)
+            }
+        }
+
+        let error = Table::<User>::new("users", UserField::Label).err().unwrap();
+        assert_eq!(error, SqlError::InvalidKey("label"));
+        assert_eq!(
+            error.to_string(),
+            "Table key label must be a stored field, not virtual or opaque"
+        );
+        assert!(Table::<User>::new("users", UserField::Id).is_ok());
+    }
 }
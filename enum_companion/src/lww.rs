@@ -0,0 +1,135 @@
+use crate::{EnumCompanionField, EnumCompanionTrait};
+
+/// The version of a write to an [`LwwRecord`]: a Lamport clock and the id of the replica
+/// which made the write.
+///
+/// Stamps are ordered by clock, then by replica id, so that concurrent writes with the same
+/// clock are resolved the same way on every replica.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stamp {
+    /// The Lamport clock of the write.
+    pub clock: u64,
+    /// The id of the replica which made the write.
+    pub replica: u64,
+}
+
+/// A last-writer-wins register map replicating a struct deriving `EnumCompanion` field by
+/// field.
+///
+/// Each field holds its latest value with the [`Stamp`] of the write. Merging two records
+/// keeps, for each field, the value with the greatest stamp, which makes
+/// [`merge`](Self::merge) commutative, associative and idempotent: replicas exchanging their
+/// writes in any order end up with the same struct.
+///
+/// Virtual and opaque fields are not replicated. [`to_struct`](Self::to_struct) takes them
+/// from the struct the record was created with.
+pub struct LwwRecord<S: EnumCompanionTrait> {
+    replica: u64,
+    clock: u64,
+    /// The struct the record was created from, providing the fields which aren't replicated.
+    template: S,
+    /// The latest write to each field, indexed by field index. `None` for virtual and
+    /// opaque fields.
+    entries: Vec<Option<(Stamp, S::Value)>>,
+}
+
+impl<S> LwwRecord<S>
+where
+    S: EnumCompanionTrait + Clone,
+    S::Value: Clone,
+{
+    /// Creates a record for replica `replica`, holding the values of `initial` with clock 0.
+    pub fn new(replica: u64, initial: S) -> Self {
+        let stamp = Stamp { clock: 0, replica };
+        let entries = S::fields()
+            .iter()
+            .map(|field| {
+                (!field.is_virtual() && !field.is_opaque()).then(|| (stamp, initial.value(*field)))
+            })
+            .collect();
+        Self {
+            replica,
+            clock: 0,
+            template: initial,
+            entries,
+        }
+    }
+
+    /// Returns the id of the replica.
+    pub fn replica(&self) -> u64 {
+        self.replica
+    }
+
+    /// Returns the Lamport clock, the greatest clock written or merged so far.
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    /// Returns the value of a field, or `None` for a field which isn't replicated.
+    pub fn get(&self, field: S::Field) -> Option<&S::Value> {
+        self.entries[field.index()].as_ref().map(|(_, value)| value)
+    }
+
+    /// Returns the stamp of the latest write to a field.
+    pub fn stamp(&self, field: S::Field) -> Option<Stamp> {
+        self.entries[field.index()]
+            .as_ref()
+            .map(|(stamp, _)| *stamp)
+    }
+
+    /// Writes the value of a field, and returns the stamp of the write.
+    ///
+    /// Returns `None`, without writing, for a field which isn't replicated.
+    pub fn set(&mut self, value: S::Value) -> Option<Stamp> {
+        let index = S::field_of(&value).index();
+        let entry = self.entries[index].as_mut()?;
+        self.clock += 1;
+        let stamp = Stamp {
+            clock: self.clock,
+            replica: self.replica,
+        };
+        *entry = (stamp, value);
+        Some(stamp)
+    }
+
+    /// Merges the writes of another replica.
+    pub fn merge(&mut self, other: &Self) {
+        self.apply_delta(other.entries.iter().flatten().cloned());
+    }
+
+    /// Returns the writes with a clock greater than `clock`, to send to other replicas.
+    ///
+    /// A replica which has merged every write with a clock up to `clock` is up to date after
+    /// applying the delta.
+    pub fn delta_since(&self, clock: u64) -> Vec<(Stamp, S::Value)> {
+        self.entries
+            .iter()
+            .flatten()
+            .filter(|(stamp, _)| stamp.clock > clock)
+            .cloned()
+            .collect()
+    }
+
+    /// Merges writes received from other replicas, e.g. from [`delta_since`](Self::delta_since).
+    pub fn apply_delta(&mut self, delta: impl IntoIterator<Item = (Stamp, S::Value)>) {
+        for (stamp, value) in delta {
+            self.clock = self.clock.max(stamp.clock);
+            let index = S::field_of(&value).index();
+            if let Some(entry) = &mut self.entries[index]
+                && stamp > entry.0
+            {
+                *entry = (stamp, value);
+            }
+        }
+    }
+
+    /// Returns the struct with the latest value of every replicated field.
+    pub fn to_struct(&self) -> S {
+        let mut inner = self.template.clone();
+        for (_, value) in self.entries.iter().flatten() {
+            inner.update(value.clone());
+        }
+        inner
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{EnumCompanionField, EnumCompanionTrait};
+use std::fmt;
+
+/// A field changed differently on both sides of a [`merge3`].
+pub struct Conflict<S: EnumCompanionTrait> {
+    /// The conflicting field.
+    pub field: S::Field,
+    /// Our value of the field, which the merged struct keeps.
+    pub ours: S::Value,
+    /// Their value of the field.
+    pub theirs: S::Value,
+}
+
+/// The result of a [`merge3`].
+pub struct MergeResult<S: EnumCompanionTrait> {
+    /// The merged struct. Conflicting fields keep our value.
+    pub merged: S,
+    /// The fields changed differently on both sides, without a `merge` policy.
+    pub conflicts: Vec<Conflict<S>>,
+}
+
+// `derive` would bound `S` itself, while the fields only need the field and value enums to
+// implement the traits.
+impl<S: EnumCompanionTrait> fmt::Debug for Conflict<S>
+where
+    S::Field: fmt::Debug,
+    S::Value: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Conflict")
+            .field("field", &self.field)
+            .field("ours", &self.ours)
+            .field("theirs", &self.theirs)
+            .finish()
+    }
+}
+
+impl<S: EnumCompanionTrait> Clone for Conflict<S>
+where
+    S::Value: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            field: self.field,
+            ours: self.ours.clone(),
+            theirs: self.theirs.clone(),
+        }
+    }
+}
+
+impl<S: EnumCompanionTrait> PartialEq for Conflict<S>
+where
+    S::Field: PartialEq,
+    S::Value: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.field == other.field && self.ours == other.ours && self.theirs == other.theirs
+    }
+}
+
+impl<S: EnumCompanionTrait + fmt::Debug> fmt::Debug for MergeResult<S>
+where
+    S::Field: fmt::Debug,
+    S::Value: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MergeResult")
+            .field("merged", &self.merged)
+            .field("conflicts", &self.conflicts)
+            .finish()
+    }
+}
+
+impl<S: EnumCompanionTrait + Clone> Clone for MergeResult<S>
+where
+    S::Value: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            merged: self.merged.clone(),
+            conflicts: self.conflicts.clone(),
+        }
+    }
+}
+
+impl<S: EnumCompanionTrait + PartialEq> PartialEq for MergeResult<S>
+where
+    S::Field: PartialEq,
+    S::Value: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.merged == other.merged && self.conflicts == other.conflicts
+    }
+}
+
+impl<S: EnumCompanionTrait> MergeResult<S> {
+    /// Returns `true` if there was no conflict.
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Merges two versions of a struct, `ours` and `theirs`, derived from a common `base`.
+///
+/// Each field changed on one side only takes the changed value. A field changed on both
+/// sides to the same value takes that value. A field changed on both sides to different
+/// values is resolved with the field's `#[companion(merge = "...")]` policy, see
+/// [`EnumCompanionTrait::resolve_conflict`], or reported as a [`Conflict`].
+///
+/// Virtual fields, which are computed from the stored ones, and opaque fields, which have no
+/// value, keep our version.
+pub fn merge3<S>(base: &S, ours: &S, theirs: &S) -> MergeResult<S>
+where
+    S: EnumCompanionTrait + Clone,
+    S::Value: PartialEq,
+{
+    let mut merged = ours.clone();
+    let mut conflicts = Vec::new();
+    for &field in S::fields() {
+        if field.is_virtual() || field.is_opaque() {
+            continue;
+        }
+        let base_value = base.value(field);
+        let our_value = ours.value(field);
+        let their_value = theirs.value(field);
+        if our_value == their_value || their_value == base_value {
+            continue;
+        }
+        if our_value == base_value {
+            merged.update(their_value);
+        } else if let Some(value) = S::resolve_conflict(&base_value, &our_value, &their_value) {
+            merged.update(value);
+        } else {
+            conflicts.push(Conflict {
+                field,
+                ours: our_value,
+                theirs: their_value,
+            });
+        }
+    }
+    MergeResult { merged, conflicts }
+}
@@ -0,0 +1,143 @@
+use crate::{EnumCompanionField, EnumCompanionTrait};
+use std::ops::Deref;
+
+/// A listener called with the updated field, its old value and its new value.
+type Callback<S> = Box<
+    dyn FnMut(
+        <S as EnumCompanionTrait>::Field,
+        &<S as EnumCompanionTrait>::Value,
+        &<S as EnumCompanionTrait>::Value,
+    ),
+>;
+
+struct Listener<S: EnumCompanionTrait> {
+    id: u64,
+    /// The field the listener is registered for, or `None` for all fields.
+    field: Option<S::Field>,
+    callback: Callback<S>,
+}
+
+/// A handle identifying a listener registered on an [`Observable`], used to unsubscribe it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subscription(u64);
+
+/// A wrapper around a struct deriving `EnumCompanion` that notifies listeners of changes.
+///
+/// Listeners are registered for a single field with [`subscribe`](Self::subscribe), or for
+/// all fields with [`subscribe_all`](Self::subscribe_all). Every change made through
+/// [`update`](Self::update) calls the matching listeners, in registration order, with the
+/// field, the old value and the new value. Nothing is called when the new value equals the
+/// old one, e.g. for a read-only field. Listeners of other fields changed by the update,
+/// e.g. through the setter of a virtual field, are called too.
+///
+/// The wrapped struct can be read through `Deref`, but can only be changed through the
+/// wrapper, so that no change goes unnoticed. Comparing fields and values requires the field
+/// and value enums to implement `PartialEq`, see `derive_field` and `derive_value`.
+pub struct Observable<S: EnumCompanionTrait> {
+    inner: S,
+    listeners: Vec<Listener<S>>,
+    next_id: u64,
+}
+
+impl<S: EnumCompanionTrait> Observable<S> {
+    /// Wraps a struct, without any listener.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            listeners: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Returns the wrapped struct, dropping the listeners.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Registers a listener called when `field` changes.
+    pub fn subscribe<F>(&mut self, field: S::Field, listener: F) -> Subscription
+    where
+        F: FnMut(S::Field, &S::Value, &S::Value) + 'static,
+    {
+        self.add_listener(Some(field), Box::new(listener))
+    }
+
+    /// Registers a listener called when any field changes.
+    pub fn subscribe_all<F>(&mut self, listener: F) -> Subscription
+    where
+        F: FnMut(S::Field, &S::Value, &S::Value) + 'static,
+    {
+        self.add_listener(None, Box::new(listener))
+    }
+
+    /// Removes a listener. Returns `false` if it was already removed.
+    pub fn unsubscribe(&mut self, subscription: Subscription) -> bool {
+        let len = self.listeners.len();
+        self.listeners
+            .retain(|listener| listener.id != subscription.0);
+        self.listeners.len() != len
+    }
+
+    fn add_listener(&mut self, field: Option<S::Field>, callback: Callback<S>) -> Subscription {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.listeners.push(Listener {
+            id,
+            field,
+            callback,
+        });
+        Subscription(id)
+    }
+}
+
+impl<S> Observable<S>
+where
+    S: EnumCompanionTrait,
+    S::Field: PartialEq,
+    S::Value: PartialEq,
+{
+    /// Updates the value of a field, and notifies the listeners of every field whose value
+    /// changed.
+    ///
+    /// The old and new values are read back from the struct, so listeners see the value as
+    /// stored, e.g. after a `set_with` function normalized it. When the struct has virtual
+    /// fields, every field is compared, since the setter of a virtual field can change stored
+    /// fields, and a virtual field changes with the stored fields it is computed from. The
+    /// changed fields are notified in field order.
+    pub fn update(&mut self, value: S::Value) {
+        let updated = S::field_of(&value);
+        let fields: Vec<S::Field> = if S::fields().iter().any(|field| field.is_virtual()) {
+            S::fields()
+                .iter()
+                .copied()
+                .filter(|field| !field.is_opaque())
+                .collect()
+        } else {
+            vec![updated]
+        };
+        let old: Vec<S::Value> = fields
+            .iter()
+            .map(|&field| self.inner.value(field))
+            .collect();
+        self.inner.update(value);
+        for (&field, old) in fields.iter().zip(&old) {
+            let new = self.inner.value(field);
+            if *old == new {
+                continue;
+            }
+            for listener in &mut self.listeners {
+                if listener.field.is_none_or(|f| f == field) {
+                    (listener.callback)(field, old, &new);
+                }
+            }
+        }
+    }
+}
+
+impl<S: EnumCompanionTrait> Deref for Observable<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.inner
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{BatchError, EnumCompanionField, EnumCompanionTrait};
+use std::sync::{PoisonError, RwLock, RwLockWriteGuard};
+
+/// A thread-safe store for a struct deriving `EnumCompanion`, with one lock per field.
+///
+/// Each stored field lives behind its own `RwLock`, so reading or writing one field never
+/// waits for another field. Operations spanning several fields, [`snapshot`](Self::snapshot)
+/// and [`update_many`](Self::update_many), lock the fields they need in field enum order,
+/// which makes them consistent with each other and with single-field updates. All methods
+/// take `&self`, so the store can be shared between threads in an `Arc`.
+///
+/// Virtual fields are computed from a snapshot, and updates to them lock every field. Opaque
+/// fields keep the value they had when the store was created.
+pub struct Shared<S: EnumCompanionTrait> {
+    /// The struct the store was created from, providing the opaque fields of snapshots.
+    template: S,
+    /// The value of each stored field, indexed by field index. `None` for virtual and opaque
+    /// fields.
+    slots: Vec<Option<RwLock<S::Value>>>,
+}
+
+impl<S> Shared<S>
+where
+    S: EnumCompanionTrait + Clone,
+    S::Value: Clone,
+{
+    /// Creates a store holding the values of `inner`.
+    pub fn new(inner: S) -> Self {
+        let slots = S::fields()
+            .iter()
+            .map(|field| {
+                (!field.is_virtual() && !field.is_opaque())
+                    .then(|| RwLock::new(inner.value(*field)))
+            })
+            .collect();
+        Self {
+            template: inner,
+            slots,
+        }
+    }
+
+    /// Returns the value of a field.
+    ///
+    /// Panics if the field is opaque, like the struct's `value` method.
+    pub fn get(&self, field: S::Field) -> S::Value {
+        match &self.slots[field.index()] {
+            Some(slot) => slot.read().unwrap_or_else(PoisonError::into_inner).clone(),
+            None => self.snapshot().value(field),
+        }
+    }
+
+    /// Updates the value of a field. Like the struct's `update` method, updates to read-only
+    /// fields are ignored.
+    pub fn set(&self, value: S::Value) {
+        let field = S::field_of(&value);
+        match &self.slots[field.index()] {
+            Some(slot) => *slot.write().unwrap_or_else(PoisonError::into_inner) = value,
+            None => self.apply(&[field], vec![value]),
+        }
+    }
+
+    /// Returns a copy of the struct with the current values of all fields.
+    pub fn snapshot(&self) -> S {
+        let guards: Vec<_> = self
+            .slots
+            .iter()
+            .flatten()
+            .map(|slot| slot.read().unwrap_or_else(PoisonError::into_inner))
+            .collect();
+        let mut snapshot = self.template.clone();
+        for value in guards.iter() {
+            snapshot.update((**value).clone());
+        }
+        snapshot
+    }
+
+    /// Updates several fields at once, so that no snapshot sees some of the values but not
+    /// the others. If any value is for a read-only field, none is applied.
+    pub fn update_many(
+        &self,
+        values: impl IntoIterator<Item = S::Value>,
+    ) -> Result<(), BatchError> {
+        let values: Vec<S::Value> = values.into_iter().collect();
+        let mut fields = Vec::with_capacity(values.len());
+        for value in &values {
+            let field = S::field_of(value);
+            if field.is_read_only() {
+                return Err(BatchError::ReadOnly(field.name()));
+            }
+            fields.push(field);
+        }
+        self.apply(&fields, values);
+        Ok(())
+    }
+
+    /// Applies `values`, for `fields`, with the locks of all affected fields held.
+    fn apply(&self, fields: &[S::Field], values: Vec<S::Value>) {
+        // A virtual field may write to any stored field.
+        let has_virtual = fields.iter().any(|field| field.is_virtual());
+        let mut indices: Vec<usize> = if has_virtual {
+            (0..self.slots.len()).collect()
+        } else {
+            fields.iter().map(|field| field.index()).collect()
+        };
+        indices.sort_unstable();
+        indices.dedup();
+
+        // Locks are always taken in index order, so concurrent updates can't deadlock.
+        let mut guards: Vec<(usize, RwLockWriteGuard<'_, S::Value>)> = indices
+            .into_iter()
+            .filter_map(|index| {
+                let slot = self.slots[index].as_ref()?;
+                Some((index, slot.write().unwrap_or_else(PoisonError::into_inner)))
+            })
+            .collect();
+
+        if has_virtual {
+            let mut inner = self.template.clone();
+            for (_, guard) in &guards {
+                inner.update((**guard).clone());
+            }
+            for value in values {
+                inner.update(value);
+            }
+            for (index, guard) in &mut guards {
+                **guard = inner.value(S::fields()[*index]);
+            }
+        } else {
+            for value in values {
+                let index = S::field_of(&value).index();
+                if let Some((_, guard)) = guards.iter_mut().find(|(i, _)| *i == index) {
+                    **guard = value;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! SQL statements built from the metadata of structs deriving `EnumCompanion`.
+//!
+//! A [`Table`] maps a struct to a database table with one column per stored field, named
+//! after the field or its `#[companion(column = "...")]`. Statements are returned as plain
+//! SQL text with their parameters as values of the struct's value enum, to be bound with any
+//! driver. Virtual and opaque fields have no column.
+
+use crate::{EnumCompanionField, EnumCompanionTrait, Primitive, SequenceKind, TypeInfo};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// The error returned when a [`Table`] can't be created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SqlError {
+    /// The key, named here, is a virtual or opaque field, which has no column.
+    InvalidKey(&'static str),
+}
+
+impl fmt::Display for SqlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SqlError::InvalidKey(name) => write!(
+                f,
+                "Table key {} must be a stored field, not virtual or opaque",
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SqlError {}
+
+/// The SQL dialect of the generated statements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    /// SQLite: `?` placeholders, and SQLite column types.
+    #[default]
+    Sqlite,
+    /// PostgreSQL: `$1`, `$2`, ... placeholders, and PostgreSQL column types.
+    Postgres,
+}
+
+/// A SQL statement and the values to bind to its placeholders, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement<V> {
+    /// The SQL text.
+    pub sql: String,
+    /// The values of the placeholders.
+    pub params: Vec<V>,
+}
+
+/// A database table storing structs of type `S`, with `key` as primary key.
+pub struct Table<S: EnumCompanionTrait> {
+    name: String,
+    key: S::Field,
+    dialect: Dialect,
+    _marker: PhantomData<fn() -> S>,
+}
+
+/// Quotes an identifier, so that reserved words and any characters can be used.
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Returns `true` if the field is stored in a column.
+fn is_column(field: &dyn EnumCompanionField) -> bool {
+    !field.is_virtual() && !field.is_opaque()
+}
+
+impl<S: EnumCompanionTrait> Table<S> {
+    /// Creates a table named `name`, with the column of `key` as primary key, using the
+    /// SQLite dialect.
+    ///
+    /// Returns [`SqlError::InvalidKey`] if `key` is a virtual or opaque field, which has no
+    /// column.
+    pub fn new(name: impl Into<String>, key: S::Field) -> Result<Self, SqlError> {
+        if !is_column(&key) {
+            return Err(SqlError::InvalidKey(key.name()));
+        }
+        Ok(Self {
+            name: name.into(),
+            key,
+            dialect: Dialect::Sqlite,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Sets the SQL dialect of the generated statements.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Returns the fields stored in a column, in field enum order.
+    pub fn fields(&self) -> impl Iterator<Item = S::Field> {
+        S::fields().iter().copied().filter(|field| is_column(field))
+    }
+
+    /// Returns the column names, in field enum order.
+    pub fn columns(&self) -> Vec<&'static str> {
+        self.fields().map(|field| field.column()).collect()
+    }
+
+    /// Returns the placeholder for the parameter at `index`, starting at 0.
+    fn placeholder(&self, index: usize) -> String {
+        match self.dialect {
+            Dialect::Sqlite => "?".to_string(),
+            Dialect::Postgres => format!("${}", index + 1),
+        }
+    }
+
+    /// Returns the column type for a field type, and whether it is nullable.
+    fn column_type(&self, info: &TypeInfo) -> (&'static str, bool) {
+        let postgres = self.dialect == Dialect::Postgres;
+        let sql_type = match info {
+            TypeInfo::Option(inner) => return (self.column_type(inner).0, true),
+            TypeInfo::Primitive(Primitive::Bool) => "BOOLEAN",
+            TypeInfo::Primitive(primitive) if primitive.is_integer() && !postgres => "INTEGER",
+            TypeInfo::Primitive(Primitive::I8 | Primitive::I16 | Primitive::U8) => "SMALLINT",
+            TypeInfo::Primitive(Primitive::I32 | Primitive::U16) => "INTEGER",
+            TypeInfo::Primitive(Primitive::I64 | Primitive::Isize | Primitive::U32) => "BIGINT",
+            TypeInfo::Primitive(primitive) if primitive.is_integer() => "NUMERIC",
+            TypeInfo::Primitive(Primitive::F32) => "REAL",
+            TypeInfo::Primitive(Primitive::F64) if postgres => "DOUBLE PRECISION",
+            TypeInfo::Primitive(Primitive::F64) => "REAL",
+            TypeInfo::Sequence {
+                kind: SequenceKind::Vec | SequenceKind::Slice | SequenceKind::Array(_),
+                element: TypeInfo::Primitive(Primitive::U8),
+            } if postgres => "BYTEA",
+            TypeInfo::Sequence {
+                kind: SequenceKind::Vec | SequenceKind::Slice | SequenceKind::Array(_),
+                element: TypeInfo::Primitive(Primitive::U8),
+            } => "BLOB",
+            TypeInfo::Reference { inner, .. } => return self.column_type(inner),
+            _ => "TEXT",
+        };
+        (sql_type, false)
+    }
+
+    /// Returns the `CREATE TABLE` statement for the table.
+    ///
+    /// Columns are `NOT NULL` unless the field is an `Option`. Field types without an SQL
+    /// equivalent are stored as `TEXT`, and byte vectors and arrays as blobs.
+    pub fn create_table(&self) -> String {
+        let columns: Vec<String> = self
+            .fields()
+            .map(|field| {
+                let (sql_type, nullable) = self.column_type(field.type_info());
+                let mut column = format!("{} {}", quote(field.column()), sql_type);
+                if field.index() == self.key.index() {
+                    column.push_str(" PRIMARY KEY");
+                } else if !nullable {
+                    column.push_str(" NOT NULL");
+                }
+                column
+            })
+            .collect();
+        format!(
+            "CREATE TABLE {} ({})",
+            quote(&self.name),
+            columns.join(", ")
+        )
+    }
+
+    /// Returns the `SELECT` statement reading every column of every row.
+    pub fn select(&self) -> String {
+        let columns: Vec<String> = self.columns().into_iter().map(quote).collect();
+        format!("SELECT {} FROM {}", columns.join(", "), quote(&self.name))
+    }
+
+    /// Returns the `INSERT` statement storing `s` as a new row.
+    pub fn insert(&self, s: &S) -> Statement<S::Value> {
+        let fields: Vec<S::Field> = self.fields().collect();
+        let columns: Vec<String> = fields.iter().map(|field| quote(field.column())).collect();
+        let placeholders: Vec<String> = (0..fields.len())
+            .map(|index| self.placeholder(index))
+            .collect();
+        Statement {
+            sql: format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                quote(&self.name),
+                columns.join(", "),
+                placeholders.join(", ")
+            ),
+            params: fields.into_iter().map(|field| s.value(field)).collect(),
+        }
+    }
+
+    /// Returns the `UPDATE` statement writing the `changed` fields of `s` to its row,
+    /// identified by the key of `s`.
+    ///
+    /// Fields without a column and the key are ignored. Returns `None` if no column is left
+    /// to update.
+    pub fn update(&self, s: &S, changed: &[S::Field]) -> Option<Statement<S::Value>> {
+        let mut fields: Vec<S::Field> = Vec::new();
+        for field in changed {
+            if is_column(field)
+                && field.index() != self.key.index()
+                && !fields.iter().any(|other| other.index() == field.index())
+            {
+                fields.push(*field);
+            }
+        }
+        if fields.is_empty() {
+            return None;
+        }
+        let assignments: Vec<String> = fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| {
+                format!("{} = {}", quote(field.column()), self.placeholder(index))
+            })
+            .collect();
+        let sql = format!(
+            "UPDATE {} SET {} WHERE {} = {}",
+            quote(&self.name),
+            assignments.join(", "),
+            quote(self.key.column()),
+            self.placeholder(fields.len())
+        );
+        let mut params: Vec<S::Value> = fields.into_iter().map(|field| s.value(field)).collect();
+        params.push(s.value(self.key));
+        Some(Statement { sql, params })
+    }
+}
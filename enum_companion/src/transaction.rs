@@ -0,0 +1,111 @@
+use crate::{EnumCompanionField, EnumCompanionTrait};
+use std::fmt;
+use std::ops::Deref;
+
+/// The error returned when a batch of updates is rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchError {
+    /// A value was given for a read-only field, named here.
+    ReadOnly(&'static str),
+    /// The validation function rejected the updated struct, with this message.
+    Invalid(String),
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchError::ReadOnly(name) => write!(f, "Field {} is read-only", name),
+            BatchError::Invalid(message) => write!(f, "Invalid update: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+/// A validation function run on the updated struct when a transaction is committed.
+type Validator<'a, S> = Box<dyn FnOnce(&S) -> Result<(), String> + 'a>;
+
+/// A function restoring a field to its state before an update.
+type Restore<'a, S> = Box<dyn FnOnce(&mut S) + 'a>;
+
+/// A guard applying a batch of updates to a struct deriving `EnumCompanion` all at once.
+///
+/// Values set through the transaction are applied to the struct immediately, so that the
+/// struct can be read through `Deref` in between, but the previous values are kept, as they
+/// were stored, so fields with `set_with` are restored exactly. Unless
+/// [`commit`](Self::commit) succeeds, they are restored when the transaction is dropped,
+/// including when an error is propagated with `?`.
+///
+/// A transaction is started with the generated `begin` method, or with [`Transaction::new`].
+pub struct Transaction<'a, S: EnumCompanionTrait> {
+    target: &'a mut S,
+    /// The functions restoring the fields updated by the transaction, in the order they
+    /// were updated.
+    saved: Vec<Restore<'a, S>>,
+    validator: Option<Validator<'a, S>>,
+}
+
+impl<'a, S: EnumCompanionTrait> Transaction<'a, S> {
+    /// Starts a transaction on `target`.
+    pub fn new(target: &'a mut S) -> Self {
+        Self {
+            target,
+            saved: Vec::new(),
+            validator: None,
+        }
+    }
+
+    /// Sets a function validating the updated struct, run once by [`commit`](Self::commit).
+    pub fn with_validator<F>(mut self, validator: F) -> Self
+    where
+        F: FnOnce(&S) -> Result<(), String> + 'a,
+    {
+        self.validator = Some(Box::new(validator));
+        self
+    }
+
+    /// Sets the value of a field.
+    ///
+    /// Returns an error, without changing the struct, if the field is read-only.
+    pub fn set(&mut self, value: S::Value) -> Result<(), BatchError> {
+        let field = S::field_of(&value);
+        if field.is_read_only() {
+            return Err(BatchError::ReadOnly(field.name()));
+        }
+        let restore = self.target.update_undoable(value);
+        self.saved.push(restore);
+        Ok(())
+    }
+
+    /// Runs the validation function, if any, and keeps the updates if it succeeds.
+    ///
+    /// If validation fails, the previous values are restored.
+    pub fn commit(mut self) -> Result<(), BatchError> {
+        if let Some(validator) = self.validator.take() {
+            validator(self.target).map_err(BatchError::Invalid)?;
+        }
+        self.saved.clear();
+        Ok(())
+    }
+
+    /// Restores the previous values. This is the same as dropping the transaction.
+    pub fn rollback(self) {}
+}
+
+impl<S: EnumCompanionTrait> Deref for Transaction<'_, S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        self.target
+    }
+}
+
+impl<S: EnumCompanionTrait> Drop for Transaction<'_, S> {
+    fn drop(&mut self) {
+        // Restoring in reverse order leaves each field with its value from before the
+        // transaction, even if it was set more than once.
+        while let Some(restore) = self.saved.pop() {
+            restore(self.target);
+        }
+    }
+}
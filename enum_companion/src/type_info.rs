@@ -0,0 +1,129 @@
+/// A structured description of a field's type, computed at derive time from the field's
+/// declared type.
+///
+/// This lets generic code tell e.g. an optional integer from a list of strings without
+/// parsing the string returned by [`EnumCompanionField::type_str`](crate::EnumCompanionField::type_str).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeInfo {
+    /// A primitive scalar type, `String` or `str`.
+    Primitive(Primitive),
+    /// An `Option<T>`.
+    Option(&'static TypeInfo),
+    /// A sequence of elements: `Vec<T>`, `VecDeque<T>`, `HashSet<T>`, `BTreeSet<T>`, `[T]` or `[T; N]`.
+    Sequence {
+        kind: SequenceKind,
+        element: &'static TypeInfo,
+    },
+    /// A map: `HashMap<K, V>` or `BTreeMap<K, V>`.
+    Map {
+        key: &'static TypeInfo,
+        value: &'static TypeInfo,
+    },
+    /// A tuple. The unit type `()` is the empty tuple.
+    Tuple(&'static [TypeInfo]),
+    /// A reference, `&T` or `&mut T`.
+    Reference {
+        mutable: bool,
+        inner: &'static TypeInfo,
+    },
+    /// A generic type parameter of the struct, by name.
+    Generic(&'static str),
+    /// Any other type, as written in the struct definition.
+    Opaque(&'static str),
+}
+
+/// The primitive kinds recognized by [`TypeInfo::Primitive`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Primitive {
+    Bool,
+    Char,
+    /// `String` or `str`.
+    Str,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    Isize,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    Usize,
+    F32,
+    F64,
+}
+
+/// The kind of collection described by [`TypeInfo::Sequence`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SequenceKind {
+    /// `Vec<T>`.
+    Vec,
+    /// `VecDeque<T>`.
+    Deque,
+    /// `HashSet<T>` or `BTreeSet<T>`.
+    Set,
+    /// `[T]`.
+    Slice,
+    /// `[T; N]`.
+    Array(usize),
+}
+
+impl TypeInfo {
+    /// Returns the primitive kind if this is a primitive type.
+    pub fn primitive(&self) -> Option<Primitive> {
+        match self {
+            TypeInfo::Primitive(primitive) => Some(*primitive),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if this is an `Option<T>`.
+    pub fn is_option(&self) -> bool {
+        matches!(self, TypeInfo::Option(_))
+    }
+}
+
+impl Primitive {
+    /// Returns `true` for signed and unsigned integer kinds.
+    pub fn is_integer(&self) -> bool {
+        self.is_signed_integer() || self.is_unsigned_integer()
+    }
+
+    /// Returns `true` for signed integer kinds.
+    pub fn is_signed_integer(&self) -> bool {
+        matches!(
+            self,
+            Primitive::I8
+                | Primitive::I16
+                | Primitive::I32
+                | Primitive::I64
+                | Primitive::I128
+                | Primitive::Isize
+        )
+    }
+
+    /// Returns `true` for unsigned integer kinds.
+    pub fn is_unsigned_integer(&self) -> bool {
+        matches!(
+            self,
+            Primitive::U8
+                | Primitive::U16
+                | Primitive::U32
+                | Primitive::U64
+                | Primitive::U128
+                | Primitive::Usize
+        )
+    }
+
+    /// Returns `true` for `f32` and `f64`.
+    pub fn is_float(&self) -> bool {
+        matches!(self, Primitive::F32 | Primitive::F64)
+    }
+
+    /// Returns `true` for integer and floating point kinds.
+    pub fn is_numeric(&self) -> bool {
+        self.is_integer() || self.is_float()
+    }
+}
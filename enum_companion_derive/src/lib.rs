@@ -4,14 +4,16 @@
 //!
 //! Please refer to the [enum_companion](https://docs.rs/enum_companion) documentation for detailed usage and examples.
 
-use darling::{FromDeriveInput, FromField, ast::Data, util::PathList};
+use darling::{FromDeriveInput, FromField, FromMeta, ast::Data, util::PathList};
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{ToTokens, quote};
 use syn::{
     DeriveInput, Ident, Type, parse_macro_input,
     visit::{self, Visit},
 };
 
+mod type_info;
+
 /// Attributes that can be applied to fields of the struct.
 #[derive(FromField, Clone)]
 #[darling(attributes(companion))]
@@ -29,9 +31,316 @@ struct FieldAttrs {
     /// The order of the field, used for sorting or display purposes.
     #[darling(default)]
     order: Option<u32>,
+    /// A stable id for the field, used as the field enum discriminant and in the binary
+    /// encoding instead of the field's position.
+    #[darling(default)]
+    id: Option<u64>,
+    /// Previous names of the field, still accepted when parsing field names.
+    #[darling(default)]
+    old_names: Vec<syn::LitStr>,
+    /// A note explaining what to use instead of the field, which marks it as deprecated.
+    #[darling(default)]
+    deprecated: Option<String>,
+    /// Redact the value of this field in the generated `Debug` and string outputs.
+    #[darling(default)]
+    sensitive: bool,
+    /// The role or level needed to read the field.
+    #[darling(default)]
+    read: Option<AccessLevel>,
+    /// The role or level needed to write the field.
+    #[darling(default)]
+    write: Option<AccessLevel>,
+    /// The name of the field's database column, if it differs from the field name.
+    #[darling(default)]
+    column: Option<String>,
     /// Skip this field from being included in the companion enums.
     #[darling(default)]
     skip: bool,
+    /// Keep this field in the field enum and metadata, but leave it out of the value enum.
+    #[darling(default)]
+    opaque: bool,
+    /// The function reading the exposed value, called with a reference to the stored value.
+    #[darling(default)]
+    get_with: Option<syn::Path>,
+    /// The function writing the exposed value, called with a mutable reference to the stored
+    /// value and the value.
+    #[darling(default)]
+    set_with: Option<syn::Path>,
+    /// The exposed type of the field, if it differs from the stored type.
+    #[darling(default)]
+    value_ty: Option<Type>,
+    /// How conflicting changes to the field are resolved by a three-way merge.
+    #[darling(default)]
+    merge: Option<MergePolicy>,
+}
+
+/// The resolution of conflicting changes to a field, given with `#[companion(merge = "...")]`.
+#[derive(Clone)]
+enum MergePolicy {
+    /// Keep our value.
+    Ours,
+    /// Take their value.
+    Theirs,
+    /// Call a function with the base, our and their values, returning the merged value.
+    With(syn::Path),
+}
+
+impl FromMeta for MergePolicy {
+    fn from_string(value: &str) -> darling::Result<Self> {
+        match value {
+            "ours" => Ok(MergePolicy::Ours),
+            "theirs" => Ok(MergePolicy::Theirs),
+            path => syn::parse_str(path)
+                .map(MergePolicy::With)
+                .map_err(|_| darling::Error::unknown_value(path)),
+        }
+    }
+}
+
+/// The access needed to read or write a field, given with `#[companion(read = ...)]` or
+/// `#[companion(write = ...)]`.
+#[derive(Clone)]
+enum AccessLevel {
+    /// A role name, written as a string.
+    Role(String),
+    /// A numeric level, written as an integer.
+    Level(u32),
+}
+
+impl FromMeta for AccessLevel {
+    fn from_string(value: &str) -> darling::Result<Self> {
+        Ok(AccessLevel::Role(value.to_string()))
+    }
+
+    fn from_value(value: &syn::Lit) -> darling::Result<Self> {
+        match value {
+            syn::Lit::Str(role) => Self::from_string(&role.value()),
+            syn::Lit::Int(level) => level
+                .base10_parse()
+                .map(AccessLevel::Level)
+                .map_err(|err| darling::Error::custom(err).with_span(level)),
+            _ => Err(darling::Error::unexpected_lit_type(value)),
+        }
+    }
+}
+
+impl AccessLevel {
+    /// Returns the `enum_companion::Access` for the level.
+    fn access(&self) -> proc_macro2::TokenStream {
+        match self {
+            AccessLevel::Role(role) => quote! { ::enum_companion::Access::Role(#role) },
+            AccessLevel::Level(level) => quote! { ::enum_companion::Access::Level(#level) },
+        }
+    }
+}
+
+/// A computed field declared on the struct with `#[companion(virtual(...))]`.
+#[derive(FromMeta, Clone)]
+struct VirtualAttrs {
+    /// The name of the field, which must be a valid identifier.
+    #[darling(with = parse_ident)]
+    name: Ident,
+    /// The type of the field value.
+    #[darling(with = parse_type)]
+    ty: Type,
+    /// The function computing the value, called with `&self`.
+    get: syn::Path,
+    /// The function applying an updated value, called with `&mut self` and the value.
+    #[darling(default)]
+    set: Option<syn::Path>,
+    /// Rename the enum variant for this field.
+    #[darling(default)]
+    rename: Option<String>,
+    /// The title of the field, used for display or documentation purposes.
+    #[darling(default)]
+    title: Option<String>,
+    /// The description of the field, used for documentation purposes.
+    #[darling(default)]
+    description: Option<String>,
+    /// The order of the field, used for sorting or display purposes.
+    #[darling(default)]
+    order: Option<u32>,
+    /// A stable id for the field, used as the field enum discriminant and in the binary
+    /// encoding instead of the field's position.
+    #[darling(default)]
+    id: Option<u64>,
+    /// Previous names of the field, still accepted when parsing field names.
+    #[darling(default)]
+    old_names: Vec<syn::LitStr>,
+    /// A note explaining what to use instead of the field, which marks it as deprecated.
+    #[darling(default)]
+    deprecated: Option<String>,
+    /// Redact the value of this field in the generated `Debug` and string outputs.
+    #[darling(default)]
+    sensitive: bool,
+    /// The role or level needed to read the field.
+    #[darling(default)]
+    read: Option<AccessLevel>,
+    /// The role or level needed to write the field.
+    #[darling(default)]
+    write: Option<AccessLevel>,
+}
+
+/// Returns `true` if the derive list contains `Serialize` or `Deserialize`.
+fn derives_serde(derives: &[syn::Path]) -> bool {
+    derives.iter().any(|path| {
+        path.segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Serialize" || segment.ident == "Deserialize")
+    })
+}
+
+/// Parses a string literal holding an identifier, e.g. the name of a virtual field.
+fn parse_ident(meta: &syn::Meta) -> darling::Result<Ident> {
+    let name = syn::LitStr::from_meta(meta)?;
+    syn::parse_str(&name.value()).map_err(|_| {
+        darling::Error::custom(format!("`{}` is not a valid identifier", name.value()))
+            .with_span(&name)
+    })
+}
+
+/// Parses the type of a virtual field, given either as a string literal or as a type, e.g.
+/// `ty = "String"` or `ty = String`.
+fn parse_type(meta: &syn::Meta) -> darling::Result<Type> {
+    match meta {
+        syn::Meta::NameValue(syn::MetaNameValue {
+            value:
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(_),
+                    ..
+                }),
+            ..
+        }) => Type::from_meta(meta),
+        syn::Meta::NameValue(name_value) => {
+            syn::parse2(name_value.value.to_token_stream()).map_err(darling::Error::from)
+        }
+        _ => Type::from_meta(meta),
+    }
+}
+
+impl VirtualAttrs {
+    /// Converts the virtual field into the attributes of an equivalent struct field.
+    fn field_attrs(&self) -> FieldAttrs {
+        FieldAttrs {
+            ident: Some(self.name.clone()),
+            ty: self.ty.clone(),
+            rename: self.rename.clone(),
+            title: self.title.clone(),
+            description: self.description.clone(),
+            order: self.order,
+            id: self.id,
+            old_names: self.old_names.clone(),
+            deprecated: self.deprecated.clone(),
+            sensitive: self.sensitive,
+            read: self.read.clone(),
+            write: self.write.clone(),
+            column: None,
+            skip: false,
+            opaque: false,
+            get_with: None,
+            set_with: None,
+            value_ty: None,
+            merge: None,
+        }
+    }
+}
+
+/// How the value of a companion field is read and written.
+enum FieldAccess {
+    /// A field stored in the struct.
+    Stored(Ident),
+    /// A field stored in the struct, read and written through `get_with` and `set_with`.
+    Mapped {
+        ident: Ident,
+        ty: Box<Type>,
+        get_with: Option<syn::Path>,
+        set_with: Option<syn::Path>,
+    },
+    /// A field stored in the struct without a value in the value enum.
+    Opaque(Ident),
+    /// A computed field, read and written through functions.
+    Virtual {
+        get: syn::Path,
+        set: Option<syn::Path>,
+    },
+}
+
+impl FieldAccess {
+    /// Returns an owned copy of the field value, given the struct as `self`.
+    fn read(&self) -> proc_macro2::TokenStream {
+        match self {
+            FieldAccess::Stored(ident) => quote! { self.#ident.clone() },
+            FieldAccess::Mapped {
+                ident,
+                get_with: Some(get_with),
+                ..
+            } => quote! { #get_with(&self.#ident) },
+            FieldAccess::Mapped { ident, .. } => quote! { self.#ident.clone() },
+            FieldAccess::Opaque(_) => unreachable!("opaque fields have no value"),
+            FieldAccess::Virtual { get, .. } => quote! { #get(self) },
+        }
+    }
+
+    /// Writes `value` to the field, given the struct as `self`.
+    fn write(&self) -> proc_macro2::TokenStream {
+        match self {
+            FieldAccess::Stored(ident) | FieldAccess::Opaque(ident) => {
+                quote! { self.#ident = value }
+            }
+            FieldAccess::Mapped {
+                ident,
+                set_with: Some(set_with),
+                ..
+            } => quote! { #set_with(&mut self.#ident, value) },
+            FieldAccess::Mapped { ident, .. } => quote! { self.#ident = value },
+            FieldAccess::Virtual { set: Some(set), .. } => quote! { #set(self, value) },
+            FieldAccess::Virtual { set: None, .. } => quote! { { let _ = value; } },
+        }
+    }
+
+    /// Writes `value` to the field, given the struct as `self`, and returns a boxed function
+    /// restoring the field's previous state. Stored fields are restored as they were stored,
+    /// without going through `set_with` again; virtual fields through their setter.
+    fn write_undoable(&self) -> proc_macro2::TokenStream {
+        match self {
+            FieldAccess::Stored(ident)
+            | FieldAccess::Opaque(ident)
+            | FieldAccess::Mapped {
+                ident,
+                set_with: None,
+                ..
+            } => quote! {{
+                let old = ::std::mem::replace(&mut self.#ident, value);
+                Box::new(move |s: &mut Self| s.#ident = old)
+            }},
+            FieldAccess::Mapped {
+                ident,
+                set_with: Some(set_with),
+                ..
+            } => quote! {{
+                let old = ::std::clone::Clone::clone(&self.#ident);
+                #set_with(&mut self.#ident, value);
+                Box::new(move |s: &mut Self| s.#ident = old)
+            }},
+            FieldAccess::Virtual {
+                get,
+                set: Some(set),
+            } => quote! {{
+                let old = #get(self);
+                #set(self, value);
+                Box::new(move |s: &mut Self| #set(s, old))
+            }},
+            FieldAccess::Virtual { set: None, .. } => quote! {{
+                let _ = value;
+                Box::new(|_: &mut Self| {})
+            }},
+        }
+    }
+
+    /// Returns `true` if the field can't be updated.
+    fn is_read_only(&self) -> bool {
+        matches!(self, FieldAccess::Virtual { set: None, .. })
+    }
 }
 
 /// Options for the `EnumCompanion` derive macro.
@@ -63,6 +372,19 @@ struct CompanionOpts {
     /// Serde attributes for the value enum.
     #[darling(default)]
     serde_value: Option<syn::Meta>,
+    /// Extra bounds on the field types passed to visitors, e.g. `"Debug + Send"`.
+    #[darling(default)]
+    visitor_bound: Option<String>,
+    /// The integer type used as the `#[repr]` of the field enum and as the type of field ids.
+    #[darling(default)]
+    field_repr: Option<Ident>,
+    /// A function called with the field and the name when a field is parsed from an old name.
+    #[darling(default)]
+    on_old_name: Option<syn::Path>,
+    /// Computed fields, which are not stored in the struct. Written `virtual(...)`, see
+    /// [`rename_keyword_options`].
+    #[darling(default, multiple, rename = "virtual_field")]
+    virtual_fields: Vec<VirtualAttrs>,
 }
 
 /// Default name for the `value` function.
@@ -83,7 +405,8 @@ fn default_fields_fn() -> String {
 #[proc_macro_derive(EnumCompanion, attributes(companion))]
 pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree.
-    let input = parse_macro_input!(input as DeriveInput);
+    let mut input = parse_macro_input!(input as DeriveInput);
+    rename_keyword_options(&mut input.attrs);
     // Parse the macro options from the derive input.
     let opts = match CompanionOpts::from_derive_input(&input) {
         Ok(val) => val,
@@ -118,21 +441,102 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
         quote! {}
     };
 
+    let visitor_bound = match opts.visitor_bound.as_deref().map(|bound| {
+        syn::parse::Parser::parse_str(
+            syn::punctuated::Punctuated::<syn::TypeParamBound, syn::Token![+]>::parse_separated_nonempty,
+            bound,
+        )
+    }) {
+        Some(Ok(bound)) => Some(bound),
+        Some(Err(err)) => {
+            return syn::Error::new(err.span(), format!("Invalid visitor_bound: {err}"))
+                .to_compile_error()
+                .into();
+        }
+        None => None,
+    };
+
+    let field_repr = opts.field_repr;
+    if let Some(repr) = &field_repr {
+        const INTEGER_TYPES: &[&str] = &[
+            "u8", "u16", "u32", "u64", "usize", "i8", "i16", "i32", "i64", "isize",
+        ];
+        if !INTEGER_TYPES.iter().any(|ty| repr == ty) {
+            return syn::Error::new(
+                repr.span(),
+                format!("Invalid field_repr: {repr}, expected an integer type"),
+            )
+            .to_compile_error()
+            .into();
+        }
+    }
+    // The type of field ids.
+    let id_ty = field_repr
+        .clone()
+        .unwrap_or_else(|| Ident::new("u16", struct_name.span()));
+    // The largest id the id type holds. `usize` and `isize` are taken as 64 bits wide.
+    let max_id: u64 = match id_ty.to_string().as_str() {
+        "u8" => u8::MAX.into(),
+        "u16" => u16::MAX.into(),
+        "u32" => u32::MAX.into(),
+        "i8" => i8::MAX as u64,
+        "i16" => i16::MAX as u64,
+        "i32" => i32::MAX as u64,
+        "i64" | "isize" => i64::MAX as u64,
+        _ => u64::MAX,
+    };
+
+    let on_old_name = opts.on_old_name;
+    let struct_name_str = struct_name.to_string();
+    // Old names are serde aliases when the field enum derives a serde trait.
+    let serde_aliases = derives_serde(&derive_field);
+
     // Get the struct's fields.
     let fields = opts.data.take_struct().unwrap();
 
     let mut field_idents = Vec::new();
+    let mut field_access = Vec::new();
     let mut field_types = Vec::new();
     let mut field_variants = Vec::new();
+    let mut field_names = Vec::new();
     let mut from_str_arms = Vec::new();
+    let mut from_name_arms = Vec::new();
     let mut field_attrs_vec = Vec::new();
 
-    // Iterate over the fields and extract the necessary information.
-    for field in fields.fields {
-        if field.skip {
-            continue;
-        }
+    // Virtual fields follow the stored fields.
+    let stored_fields = fields
+        .fields
+        .into_iter()
+        .filter(|field| !field.skip)
+        .map(|field| {
+            let ident = field.ident.clone().unwrap();
+            let access = if field.opaque {
+                FieldAccess::Opaque(ident)
+            } else if field.get_with.is_some() || field.set_with.is_some() {
+                FieldAccess::Mapped {
+                    ident,
+                    ty: Box::new(field.ty.clone()),
+                    get_with: field.get_with.clone(),
+                    set_with: field.set_with.clone(),
+                }
+            } else {
+                FieldAccess::Stored(ident)
+            };
+            (field, access)
+        });
+    let virtual_fields = opts.virtual_fields.iter().map(|field| {
+        let access = FieldAccess::Virtual {
+            get: field.get.clone(),
+            set: field.set.clone(),
+        };
+        (field.field_attrs(), access)
+    });
+
+    // The names and variant names parsed by `FromStr`, with their field.
+    let mut current_names: Vec<(String, Ident)> = Vec::new();
 
+    // Iterate over the fields and extract the necessary information.
+    for (field, access) in stored_fields.chain(virtual_fields) {
         let ident = field.ident.clone().unwrap();
         let variant_name_str = field
             .rename
@@ -145,67 +549,482 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
         if variant_name_str != ident_str {
             patterns.push(variant_name_str);
         }
+        let name = field.rename.clone().unwrap_or(ident_str);
+        current_names.extend(
+            patterns
+                .iter()
+                .map(|pattern| (pattern.clone(), ident.clone())),
+        );
 
+        // Parsing the name of a deprecated field records a warning.
+        let warn = field.deprecated.as_ref().map(|note| {
+            quote! {
+                ::enum_companion::diagnostics::record_diagnostic(
+                    ::enum_companion::Diagnostic {
+                        struct_name: #struct_name_str,
+                        field: #name,
+                        used_name: s.to_string(),
+                        note: #note,
+                    },
+                );
+            }
+        });
         from_str_arms.push(quote! {
-            #(#patterns)|* => Ok(Self::#variant)
+            #(#patterns)|* => {
+                #warn
+                Ok(Self::#variant)
+            }
         });
+        from_name_arms.push(quote! { #(#patterns)|* => Some(Self::#variant) });
+        if !field.old_names.is_empty() {
+            let old_names = &field.old_names;
+            from_name_arms.push(quote! { #(#old_names)|* => Some(Self::#variant) });
+            let report = on_old_name
+                .as_ref()
+                .map(|hook| quote! { #hook(Self::#variant, s); });
+            from_str_arms.push(quote! {
+                #(#old_names)|* => {
+                    #report
+                    #warn
+                    Ok(Self::#variant)
+                }
+            });
+        }
 
+        field_names.push(name);
         field_idents.push(ident);
-        field_types.push(field.ty.clone());
+        field_access.push(access);
+        field_types.push(field.value_ty.clone().unwrap_or_else(|| field.ty.clone()));
         field_variants.push(variant);
         field_attrs_vec.push(field);
     }
 
+    // An old name must resolve to a single field, so it can be neither the name of a field
+    // nor an old name of another.
+    let mut seen_old_names: Vec<(String, &Ident)> = Vec::new();
+    for (attrs, ident) in field_attrs_vec.iter().zip(field_idents.iter()) {
+        for old_name in &attrs.old_names {
+            let value = old_name.value();
+            let other = current_names
+                .iter()
+                .map(|(name, other)| (name, other))
+                .chain(seen_old_names.iter().map(|(name, other)| (name, *other)))
+                .find(|(name, _)| **name == value)
+                .map(|(_, other)| other);
+            if let Some(other) = other {
+                return syn::Error::new(
+                    old_name.span(),
+                    format!(
+                        "Old name \"{value}\" of field {ident} is already used by field {other}"
+                    ),
+                )
+                .to_compile_error()
+                .into();
+            }
+            seen_old_names.push((value, ident));
+        }
+    }
+
+    // An exposed type differing from the stored type can only be read and written through
+    // both conversion functions.
+    for (attrs, ident) in field_attrs_vec.iter().zip(field_idents.iter()) {
+        let (Some(value_ty), false) = (&attrs.value_ty, attrs.opaque) else {
+            continue;
+        };
+        let ty = &attrs.ty;
+        if quote!(#value_ty).to_string() == quote!(#ty).to_string() {
+            continue;
+        }
+        let missing: Vec<&str> = [
+            ("get_with", attrs.get_with.is_none()),
+            ("set_with", attrs.set_with.is_none()),
+        ]
+        .into_iter()
+        .filter(|(_, missing)| *missing)
+        .map(|(option, _)| option)
+        .collect();
+        if !missing.is_empty() {
+            return syn::Error::new_spanned(
+                value_ty,
+                format!(
+                    "value_ty of field {ident} differs from its type, and needs {} to convert it",
+                    missing.join(" and ")
+                ),
+            )
+            .to_compile_error()
+            .into();
+        }
+    }
+
+    // Field ids are either given for every field, or default to the field positions.
+    let explicit_ids = field_attrs_vec.iter().any(|attrs| attrs.id.is_some());
+    let mut field_ids: Vec<u64> = Vec::with_capacity(field_attrs_vec.len());
+    for (index, (attrs, ident)) in field_attrs_vec.iter().zip(field_idents.iter()).enumerate() {
+        let id = match attrs.id {
+            Some(id) => id,
+            None if explicit_ids => {
+                return syn::Error::new(
+                    ident.span(),
+                    format!("Missing id for field {ident}: every field needs an id when one has"),
+                )
+                .to_compile_error()
+                .into();
+            }
+            None => index as u64,
+        };
+        if id > max_id {
+            return syn::Error::new(
+                ident.span(),
+                format!("Id {id} of field {ident} does not fit in {id_ty}"),
+            )
+            .to_compile_error()
+            .into();
+        }
+        if let Some(other) = field_ids.iter().position(|&other| other == id) {
+            return syn::Error::new(
+                ident.span(),
+                format!(
+                    "Duplicate id {id} for field {ident}, already used by field {}",
+                    field_idents[other]
+                ),
+            )
+            .to_compile_error()
+            .into();
+        }
+        field_ids.push(id);
+    }
+
+    // Fields with a value in the value enum, i.e. all but the opaque fields.
+    let value_fields: Vec<(&FieldAccess, &Ident, &Ident, &Type)> = field_access
+        .iter()
+        .zip(field_idents.iter())
+        .zip(field_variants.iter())
+        .zip(field_types.iter())
+        .filter(|(((access, _), _), _)| !matches!(access, FieldAccess::Opaque(_)))
+        .map(|(((access, ident), variant), ty)| (access, ident, variant, ty))
+        .collect();
+    let value_variants: Vec<&Ident> = value_fields.iter().map(|field| field.2).collect();
+    let value_types: Vec<&Type> = value_fields.iter().map(|field| field.3).collect();
+    let value_count = value_fields.len();
+    // The ids identifying the fields in the binary encoding.
+    let value_ids: Vec<u64> = field_access
+        .iter()
+        .zip(field_ids.iter())
+        .filter(|(access, _)| !matches!(access, FieldAccess::Opaque(_)))
+        .map(|(_, id)| *id)
+        .collect();
+
+    // The fields whose values are redacted.
+    let sensitive_variants: Vec<&Ident> = field_variants
+        .iter()
+        .zip(field_attrs_vec.iter())
+        .filter(|(_, attrs)| attrs.sensitive)
+        .map(|(variant, _)| variant)
+        .collect();
+
     // Create the names for the generated enums.
     let field_enum_name = syn::Ident::new(&format!("{struct_name}Field"), struct_name.span());
     let value_enum_name = syn::Ident::new(&format!("{struct_name}Value"), struct_name.span());
 
     // Prepare the variants for the field enum.
-    let field_enum_variants = field_variants.iter();
-    let _field_variants_count = field_variants.len();
+    let field_enum_variants = field_variants
+        .iter()
+        .zip(field_ids.iter())
+        .zip(field_attrs_vec.iter())
+        .map(|((variant, id), attrs)| {
+            let aliases = attrs
+                .old_names
+                .iter()
+                .filter(|_| serde_aliases)
+                .map(|name| quote! { #[serde(alias = #name)] });
+            if explicit_ids {
+                let id = proc_macro2::Literal::u64_unsuffixed(*id);
+                quote! { #(#aliases)* #variant = #id }
+            } else {
+                quote! { #(#aliases)* #variant }
+            }
+        });
+    let field_repr_attr = field_repr.as_ref().map(|repr| quote! { #[repr(#repr)] });
+    let field_variants_count = field_variants.len();
+    let (deprecated_variants, active_variants): (Vec<_>, Vec<_>) = field_variants
+        .iter()
+        .zip(field_attrs_vec.iter())
+        .partition(|(_, attrs)| attrs.deprecated.is_some());
+    let deprecated_variants = deprecated_variants.into_iter().map(|(variant, _)| variant);
+    let active_variants = active_variants.into_iter().map(|(variant, _)| variant);
 
     // Prepare the variants for the value enum.
-    let value_enum_variants = field_variants
+    // Types using each lifetime and type parameter of the struct, for the `PhantomData` of
+    // enums which may have no other use for them.
+    let generic_markers: Vec<_> = generics
+        .params
         .iter()
-        .zip(field_types.iter())
+        .filter_map(|param| match param {
+            syn::GenericParam::Lifetime(param) => {
+                let lifetime = &param.lifetime;
+                Some(quote! { &#lifetime () })
+            }
+            syn::GenericParam::Type(param) => {
+                let ident = &param.ident;
+                Some(quote! { #ident })
+            }
+            syn::GenericParam::Const(_) => None,
+        })
+        .collect();
+
+    // Without any field with a value, an uninhabited variant keeps the generic parameters in
+    // use, and gives the matches on the value enum an arm.
+    let empty_value_variant = (value_count == 0).then(|| {
+        let serde_skip = derives_serde(&derive_value).then(|| quote! { #[serde(skip)] });
+        quote! {
+            #[doc(hidden)]
+            #serde_skip
+            __Empty(
+                ::std::convert::Infallible,
+                ::std::marker::PhantomData<fn() -> (#(#generic_markers,)*)>,
+            )
+        }
+    });
+    // The arms for the empty variant, in matches on a reference and on a value.
+    let empty_ref_arm =
+        (value_count == 0).then(|| quote! { Self::__Empty(never, _) => match *never {} });
+    let empty_value_arm = (value_count == 0)
+        .then(|| quote! { #value_enum_name::__Empty(never, _) => match never {} });
+    let value_enum_variants = value_variants
+        .iter()
+        .zip(value_types.iter())
         .map(|(variant, ty)| {
             quote! { #variant(#ty) }
-        });
+        })
+        .chain(empty_value_variant);
 
     // Prepare the match arms for the `value` function.
     let value_match_arms =
-        field_idents
+        field_access
             .iter()
             .zip(field_variants.iter())
-            .map(|(ident, variant)| {
+            .zip(field_names.iter())
+            .map(|((access, variant), name)| {
+                if let FieldAccess::Opaque(_) = access {
+                    return quote! {
+                        #field_enum_name::#variant => panic!("Field {} is opaque and has no value", #name)
+                    };
+                }
+                let read = access.read();
                 quote! {
-                    #field_enum_name::#variant => #value_enum_name::#variant(self.#ident.clone())
+                    #field_enum_name::#variant => #value_enum_name::#variant(#read)
                 }
             });
 
-    // Prepare the match arms for the `update` function.
-    let update_match_arms =
-        field_idents
+    // Prepare the match arms for the `take` function.
+    let take_match_arms =
+        field_access
             .iter()
             .zip(field_variants.iter())
-            .map(|(ident, variant)| {
+            .zip(field_names.iter())
+            .map(|((access, variant), name)| {
+                let taken = match access {
+                    FieldAccess::Stored(ident)
+                    | FieldAccess::Mapped {
+                        ident,
+                        get_with: None,
+                        ..
+                    } => quote! { ::std::mem::take(&mut self.#ident) },
+                    FieldAccess::Mapped {
+                        ident,
+                        get_with: Some(get_with),
+                        ..
+                    } => quote! { #get_with(&::std::mem::take(&mut self.#ident)) },
+                    FieldAccess::Virtual { get, .. } => quote! { #get(self) },
+                    FieldAccess::Opaque(_) => {
+                        return quote! {
+                            #field_enum_name::#variant => panic!("Field {} is opaque and has no value", #name)
+                        };
+                    }
+                };
                 quote! {
-                    #value_enum_name::#variant(value) => self.#ident = value
+                    #field_enum_name::#variant => #value_enum_name::#variant(#taken)
                 }
             });
 
+    // The `for<'__companion>` binder defers the `Default` bounds to the call site, so that
+    // `take` is only unavailable, rather than a compile error, when a field is not `Default`.
+    let take_bounds = field_access
+        .iter()
+        .zip(field_types.iter())
+        .filter_map(|(access, ty)| match access {
+            FieldAccess::Stored(_) => Some(ty),
+            FieldAccess::Mapped { ty, .. } => Some(&**ty),
+            FieldAccess::Opaque(_) | FieldAccess::Virtual { .. } => None,
+        })
+        .map(|ty| {
+            quote! { for<'__companion> #ty: ::std::default::Default }
+        });
+
+    // Prepare the match arms for the `update` function.
+    let update_match_arms = value_fields
+        .iter()
+        .map(|(access, _, variant, _)| {
+            let write = access.write();
+            quote! {
+                #value_enum_name::#variant(value) => #write
+            }
+        })
+        .chain(empty_value_arm.clone());
+
+    // Prepare the match arms for the `update_undoable` function.
+    let update_undoable_arms = value_fields
+        .iter()
+        .map(|(access, _, variant, _)| {
+            let write = access.write_undoable();
+            quote! {
+                #value_enum_name::#variant(value) => #write
+            }
+        })
+        .chain(empty_value_arm.clone());
+
+    // Stored fields without `get_with` or `set_with`, for the generated code that borrows them.
+    let stored_variants: Vec<&Ident> = field_access
+        .iter()
+        .zip(field_variants.iter())
+        .filter(|(access, _)| matches!(access, FieldAccess::Stored(_)))
+        .map(|(_, variant)| variant)
+        .collect();
+    let stored_types: Vec<&Type> = field_access
+        .iter()
+        .zip(field_types.iter())
+        .filter(|(access, _)| matches!(access, FieldAccess::Stored(_)))
+        .map(|(_, ty)| ty)
+        .collect();
+
+    let generic_param_idents: std::collections::HashSet<String> = generics
+        .params
+        .iter()
+        .filter_map(|p| match p {
+            syn::GenericParam::Type(ty) => Some(ty.ident.to_string()),
+            _ => None,
+        })
+        .collect();
+
+    let const_param_idents: std::collections::HashSet<String> = generics
+        .params
+        .iter()
+        .filter_map(|p| match p {
+            syn::GenericParam::Const(c) => Some(c.ident.to_string()),
+            _ => None,
+        })
+        .collect();
+
     let enum_companion_field_impl = {
-        let name_arms =
-            field_variants
+        let name_arms = field_variants
+            .iter()
+            .zip(field_names.iter())
+            .map(|(variant, name)| {
+                quote! { Self::#variant => #name }
+            });
+
+        let old_names_arms: Vec<_> = field_variants
+            .iter()
+            .zip(field_attrs_vec.iter())
+            .filter(|(_, attrs)| !attrs.old_names.is_empty())
+            .map(|(variant, attrs)| {
+                let old_names = &attrs.old_names;
+                quote! { Self::#variant => &[#(#old_names),*] }
+            })
+            .collect();
+        let old_names_fn = if old_names_arms.is_empty() {
+            quote! {}
+        } else {
+            quote! {
+                fn old_names(&self) -> &'static [&'static str] {
+                    match self {
+                        #(#old_names_arms,)*
+                        #[allow(unreachable_patterns)]
+                        _ => &[],
+                    }
+                }
+            }
+        };
+
+        let deprecation_arms: Vec<_> = field_variants
+            .iter()
+            .zip(field_attrs_vec.iter())
+            .filter_map(|(variant, attrs)| {
+                let note = attrs.deprecated.as_ref()?;
+                Some(quote! { Self::#variant => Some(#note) })
+            })
+            .collect();
+        let deprecation_fn = if deprecation_arms.is_empty() {
+            quote! {}
+        } else {
+            quote! {
+                fn deprecation(&self) -> Option<&'static str> {
+                    match self {
+                        #(#deprecation_arms,)*
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    }
+                }
+            }
+        };
+
+        let is_sensitive = if sensitive_variants.is_empty() {
+            quote! { false }
+        } else {
+            quote! { matches!(self, #(Self::#sensitive_variants)|*) }
+        };
+
+        let access_fn = |name: &str, level: fn(&FieldAttrs) -> &Option<AccessLevel>| {
+            let arms: Vec<_> = field_variants
                 .iter()
                 .zip(field_attrs_vec.iter())
-                .map(|(variant, attrs)| {
-                    let name = attrs
-                        .rename
-                        .clone()
-                        .unwrap_or_else(|| attrs.ident.as_ref().unwrap().to_string());
-                    quote! { Self::#variant => #name }
-                });
+                .filter_map(|(variant, attrs)| {
+                    let access = level(attrs).as_ref()?.access();
+                    Some(quote! { Self::#variant => #access })
+                })
+                .collect();
+            if arms.is_empty() {
+                return quote! {};
+            }
+            let name = Ident::new(name, proc_macro2::Span::call_site());
+            quote! {
+                fn #name(&self) -> ::enum_companion::Access {
+                    match self {
+                        #(#arms,)*
+                        #[allow(unreachable_patterns)]
+                        _ => ::enum_companion::Access::Public,
+                    }
+                }
+            }
+        };
+        let read_access_fn = access_fn("read_access", |attrs| &attrs.read);
+        let write_access_fn = access_fn("write_access", |attrs| &attrs.write);
+
+        let column_arms: Vec<_> = field_variants
+            .iter()
+            .zip(field_attrs_vec.iter())
+            .filter_map(|(variant, attrs)| {
+                let column = attrs.column.as_ref()?;
+                Some(quote! { Self::#variant => #column })
+            })
+            .collect();
+        let column_fn = if column_arms.is_empty() {
+            quote! {}
+        } else {
+            quote! {
+                fn column(&self) -> &'static str {
+                    match self {
+                        #(#column_arms,)*
+                        #[allow(unreachable_patterns)]
+                        _ => self.name(),
+                    }
+                }
+            }
+        };
+
+        let index_arms = field_variants.iter().enumerate().map(|(index, variant)| {
+            quote! { Self::#variant => #index }
+        });
 
         let type_str_arms = field_variants
             .iter()
@@ -215,6 +1034,15 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
                 quote! { Self::#variant => #type_str }
             });
 
+        let type_info_arms = field_variants
+            .iter()
+            .zip(field_types.iter())
+            .map(|(variant, ty)| {
+                let type_info =
+                    type_info::type_info_expr(ty, &generic_param_idents, &const_param_idents);
+                quote! { Self::#variant => &#type_info }
+            });
+
         let title_arms =
             field_variants
                 .iter()
@@ -243,6 +1071,42 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
                     quote! { Self::#variant => #description }
                 });
 
+        let virtual_variants: Vec<_> = field_access
+            .iter()
+            .zip(field_variants.iter())
+            .filter(|(access, _)| matches!(access, FieldAccess::Virtual { .. }))
+            .map(|(_, variant)| quote! { Self::#variant })
+            .collect();
+        let is_virtual = if virtual_variants.is_empty() {
+            quote! { false }
+        } else {
+            quote! { matches!(self, #(#virtual_variants)|*) }
+        };
+
+        let opaque_variants: Vec<_> = field_access
+            .iter()
+            .zip(field_variants.iter())
+            .filter(|(access, _)| matches!(access, FieldAccess::Opaque(_)))
+            .map(|(_, variant)| quote! { Self::#variant })
+            .collect();
+        let is_opaque = if opaque_variants.is_empty() {
+            quote! { false }
+        } else {
+            quote! { matches!(self, #(#opaque_variants)|*) }
+        };
+
+        let read_only_variants: Vec<_> = field_access
+            .iter()
+            .zip(field_variants.iter())
+            .filter(|(access, _)| access.is_read_only())
+            .map(|(_, variant)| quote! { Self::#variant })
+            .collect();
+        let is_read_only = if read_only_variants.is_empty() {
+            quote! { false }
+        } else {
+            quote! { matches!(self, #(#read_only_variants)|*) }
+        };
+
         let order_arms =
             field_variants
                 .iter()
@@ -264,6 +1128,16 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
                         #(#type_str_arms),*
                     }
                 }
+                fn type_info(&self) -> &'static ::enum_companion::TypeInfo {
+                    match self {
+                        #(#type_info_arms),*
+                    }
+                }
+                fn index(&self) -> usize {
+                    match self {
+                        #(#index_arms),*
+                    }
+                }
                 fn title(&self) -> &'static str {
                     match self {
                         #(#title_arms),*
@@ -279,30 +1153,67 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
                         #(#order_arms),*
                     }
                 }
+                fn is_virtual(&self) -> bool {
+                    #is_virtual
+                }
+                fn is_read_only(&self) -> bool {
+                    #is_read_only
+                }
+                fn is_opaque(&self) -> bool {
+                    #is_opaque
+                }
+                fn is_sensitive(&self) -> bool {
+                    #is_sensitive
+                }
+                #old_names_fn
+                #deprecation_fn
+                #read_access_fn
+                #write_access_fn
+                #column_fn
             }
         }
     };
 
     let enum_companion_value_impl = {
-        let field_name_arms =
-            field_variants
-                .iter()
-                .zip(field_idents.iter())
-                .map(|(variant, ident)| {
-                    let ident_str = ident.to_string();
-                    quote! { Self::#variant(_) => #ident_str }
-                });
+        let field_name_arms = value_fields
+            .iter()
+            .map(|(_, ident, variant, _)| {
+                let ident_str = ident.to_string();
+                quote! { Self::#variant(_) => #ident_str }
+            })
+            .chain(empty_ref_arm.clone());
 
-        let type_name_arms = field_variants
+        let type_name_arms = value_fields
             .iter()
-            .zip(field_types.iter())
-            .map(|(variant, ty)| {
+            .map(|(_, _, variant, ty)| {
                 let type_str = quote!(#ty).to_string();
                 quote! { Self::#variant(_) => #type_str }
-            });
+            })
+            .chain(empty_ref_arm.clone());
+
+        let field_arms = value_variants
+            .iter()
+            .map(|variant| {
+                quote! { Self::#variant(_) => #field_enum_name::#variant }
+            })
+            .chain(empty_ref_arm.clone());
 
         quote! {
+            impl #impl_generics #value_enum_name #ty_generics #where_clause {
+                /// Returns the field this value belongs to.
+                pub fn field(&self) -> #field_enum_name {
+                    match self {
+                        #(#field_arms),*
+                    }
+                }
+            }
+
             impl #impl_generics ::enum_companion::EnumCompanionValue for #value_enum_name #ty_generics #where_clause {
+                type Field = #field_enum_name;
+
+                fn field(&self) -> #field_enum_name {
+                    Self::field(self)
+                }
                 fn field_name(&self) -> &'static str {
                     match self {
                         #(#field_name_arms),*
@@ -317,26 +1228,407 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
         }
     };
 
-    let trait_impl = if opts.value_fn == "value"
-        && opts.update_fn == "update"
-        && opts.fields_fn == "fields"
-    {
+    // Fields with a merge policy resolve their conflicts, the others report them.
+    let resolve_conflict_arms: Vec<_> = value_variants
+        .iter()
+        .zip(field_attrs_vec.iter().filter(|attrs| !attrs.opaque))
+        .filter_map(|(variant, attrs)| {
+            let resolved = match attrs.merge.as_ref()? {
+                MergePolicy::Ours => quote! { ours.clone() },
+                MergePolicy::Theirs => quote! { theirs.clone() },
+                MergePolicy::With(path) => quote! { #path(base, ours, theirs) },
+            };
+            Some(quote! {
+                (
+                    #value_enum_name::#variant(base),
+                    #value_enum_name::#variant(ours),
+                    #value_enum_name::#variant(theirs),
+                ) => Some(#value_enum_name::#variant(#resolved))
+            })
+        })
+        .collect();
+    let resolve_conflict_fn = if resolve_conflict_arms.is_empty() {
+        quote! {}
+    } else {
         quote! {
-            impl #impl_generics ::enum_companion::EnumCompanionTrait<#field_enum_name, #value_enum_name #ty_generics> for #struct_name #ty_generics #where_clause {
-                fn value(&self, field: #field_enum_name) -> #value_enum_name #ty_generics {
-                    self.value(field)
+            #[allow(unused_variables, unreachable_patterns)]
+            fn resolve_conflict(
+                base: &#value_enum_name #ty_generics,
+                ours: &#value_enum_name #ty_generics,
+                theirs: &#value_enum_name #ty_generics,
+            ) -> Option<#value_enum_name #ty_generics> {
+                match (base, ours, theirs) {
+                    #(#resolve_conflict_arms,)*
+                    _ => None,
                 }
+            }
+        }
+    };
+
+    let trait_impl = quote! {
+        impl #impl_generics ::enum_companion::EnumCompanionTrait for #struct_name #ty_generics #where_clause {
+            type Field = #field_enum_name;
+            type Value = #value_enum_name #ty_generics;
+
+            fn value(&self, field: #field_enum_name) -> #value_enum_name #ty_generics {
+                Self::#value_fn_name(self, field)
+            }
+
+            fn update(&mut self, value: #value_enum_name #ty_generics) {
+                Self::#update_fn_name(self, value)
+            }
+
+            fn fields() -> &'static [#field_enum_name] {
+                &#field_enum_name::FIELDS
+            }
+
+            fn as_values(&self) -> Vec<#value_enum_name #ty_generics> {
+                Self::as_values(self)
+            }
 
-                fn update(&mut self, value: #value_enum_name #ty_generics) {
-                    self.update(value)
+            fn update_undoable<'__undo>(
+                &mut self,
+                value: #value_enum_name #ty_generics,
+            ) -> ::std::boxed::Box<dyn FnOnce(&mut Self) + '__undo>
+            where
+                Self: '__undo,
+            {
+                match value {
+                    #(#update_undoable_arms),*
                 }
+            }
 
-                fn fields() -> &'static [#field_enum_name] {
-                    &#field_enum_name::FIELDS
+            #resolve_conflict_fn
+        }
+    };
+
+    let value_ref_enum_name =
+        syn::Ident::new(&format!("{struct_name}ValueRef"), struct_name.span());
+    let mut ref_generics = generics.clone();
+    ref_generics
+        .params
+        .insert(0, syn::parse_quote!('__companion));
+    let (ref_impl_generics, ref_ty_generics, ref_where_clause) = ref_generics.split_for_impl();
+    // The struct's generic arguments, without angle brackets, to follow the borrow lifetime.
+    let ty_params = generics.params.iter().map(|param| match param {
+        syn::GenericParam::Lifetime(lifetime) => {
+            let lifetime = &lifetime.lifetime;
+            quote!(#lifetime)
+        }
+        syn::GenericParam::Type(ty) => {
+            let ident = &ty.ident;
+            quote!(#ident)
+        }
+        syn::GenericParam::Const(param) => {
+            let ident = &param.ident;
+            quote!(#ident)
+        }
+    });
+    let ty_params = quote!(#(#ty_params),*);
+
+    let value_ref_impl = {
+        let mut value_ref_variants: Vec<_> = stored_variants
+            .iter()
+            .zip(stored_types.iter())
+            .map(|(variant, ty)| {
+                quote! { #variant(&'__companion #ty) }
+            })
+            .collect();
+
+        let mut field_arms: Vec<_> = stored_variants
+            .iter()
+            .map(|variant| {
+                quote! { Self::#variant(_) => #field_enum_name::#variant }
+            })
+            .collect();
+
+        let mut to_value_arms: Vec<_> = stored_variants
+            .iter()
+            .map(|variant| {
+                quote! { Self::#variant(value) => #value_enum_name::#variant((*value).clone()) }
+            })
+            .collect();
+
+        // Without any borrowable field, an uninhabited variant keeps the lifetime in use.
+        if stored_variants.is_empty() {
+            value_ref_variants.push(quote! {
+                #[doc(hidden)]
+                __Empty(
+                    ::std::convert::Infallible,
+                    ::std::marker::PhantomData<(&'__companion (), fn() -> (#(#generic_markers,)*))>,
+                )
+            });
+            field_arms.push(quote! { Self::__Empty(never, _) => match *never {} });
+            to_value_arms.push(quote! { Self::__Empty(never, _) => match *never {} });
+        }
+
+        quote! {
+            /// An enum borrowing the values of the struct's fields.
+            #[allow(dead_code)]
+            #vis enum #value_ref_enum_name #ref_ty_generics {
+                #(#value_ref_variants),*
+            }
+
+            impl #ref_impl_generics ::std::clone::Clone for #value_ref_enum_name #ref_ty_generics #ref_where_clause {
+                fn clone(&self) -> Self {
+                    *self
+                }
+            }
+
+            impl #ref_impl_generics ::std::marker::Copy for #value_ref_enum_name #ref_ty_generics #ref_where_clause {}
+
+            impl #ref_impl_generics #value_ref_enum_name #ref_ty_generics #ref_where_clause {
+                /// Returns the field this value belongs to.
+                pub fn field(&self) -> #field_enum_name {
+                    match self {
+                        #(#field_arms),*
+                    }
+                }
+
+                /// Clones the borrowed value into an owned value.
+                pub fn to_value(&self) -> #value_enum_name #ty_generics {
+                    match self {
+                        #(#to_value_arms),*
+                    }
+                }
+            }
+        }
+    };
+
+    // Prepare the match arms for the `value_ref` function.
+    let value_ref_match_arms =
+        field_access
+            .iter()
+            .zip(field_variants.iter())
+            .map(|(access, variant)| match access {
+                FieldAccess::Stored(ident) => quote! {
+                    #field_enum_name::#variant => Some(#value_ref_enum_name::#variant(&self.#ident))
+                },
+                FieldAccess::Mapped { .. }
+                | FieldAccess::Opaque(_)
+                | FieldAccess::Virtual { .. } => {
+                    quote! {
+                        #field_enum_name::#variant => None
+                    }
                 }
+            });
 
-                fn as_values(&self) -> Vec<#value_enum_name #ty_generics> {
-                    self.as_values()
+    // Prepare the statements for the `visit` function.
+    let visit_statements =
+        field_access
+            .iter()
+            .zip(field_variants.iter())
+            .map(|(access, variant)| match access {
+                FieldAccess::Stored(ident)
+                | FieldAccess::Opaque(ident)
+                | FieldAccess::Mapped {
+                    ident,
+                    get_with: None,
+                    ..
+                } => quote! {
+                    visitor.field(#field_enum_name::#variant, &self.#ident);
+                },
+                FieldAccess::Mapped { .. } | FieldAccess::Virtual { .. } => {
+                    let read = access.read();
+                    quote! {
+                        visitor.field(#field_enum_name::#variant, &#read);
+                    }
+                }
+            });
+
+    // Stored fields without `get_with` or `set_with`, and opaque fields, for `visit_mut`.
+    let (visit_mut_statements, visit_mut_types): (Vec<_>, Vec<_>) = field_access
+        .iter()
+        .zip(field_variants.iter())
+        .zip(field_types.iter())
+        .filter_map(|((access, variant), ty)| match access {
+            FieldAccess::Stored(ident) | FieldAccess::Opaque(ident) => Some((
+                quote! { visitor.field(#field_enum_name::#variant, &mut self.#ident); },
+                ty,
+            )),
+            FieldAccess::Mapped { .. } | FieldAccess::Virtual { .. } => None,
+        })
+        .unzip();
+
+    // Values of virtual fields are computed before the stored fields are moved out. Opaque
+    // fields are dropped.
+    let into_parts_statements =
+        value_fields
+            .iter()
+            .filter_map(|(access, ident, _, _)| match access {
+                FieldAccess::Virtual { get, .. } => Some(quote! { let #ident = #get(&self); }),
+                _ => None,
+            });
+    let into_parts_values = value_fields
+        .iter()
+        .map(|(access, ident, variant, _)| match access {
+            FieldAccess::Stored(_) | FieldAccess::Mapped { get_with: None, .. } => {
+                quote! { #value_enum_name::#variant(self.#ident) }
+            }
+            FieldAccess::Mapped {
+                get_with: Some(get_with),
+                ..
+            } => quote! { #value_enum_name::#variant(#get_with(&self.#ident)) },
+            FieldAccess::Virtual { .. } => quote! { #value_enum_name::#variant(#ident) },
+            FieldAccess::Opaque(_) => unreachable!("opaque fields have no value"),
+        });
+    let values_array_values = value_fields.iter().map(|(access, _, variant, _)| {
+        let read = access.read();
+        quote! { #value_enum_name::#variant(#read) }
+    });
+
+    // With `visitor_bound`, visitors implement struct specific traits carrying the extra bounds.
+    let (visitor_traits, visitor_path, visitor_mut_path) = if let Some(bound) = &visitor_bound {
+        let visitor_name = syn::Ident::new(&format!("{struct_name}Visitor"), struct_name.span());
+        let visitor_mut_name =
+            syn::Ident::new(&format!("{struct_name}VisitorMut"), struct_name.span());
+        (
+            quote! {
+                /// A visitor over the fields of the struct, see `enum_companion::FieldVisitor`.
+                #vis trait #visitor_name {
+                    /// Visits a single field.
+                    fn field<T: ?Sized + 'static + #bound>(&mut self, field: #field_enum_name, value: &T);
+                }
+
+                /// A mutable visitor over the fields of the struct, see `enum_companion::FieldVisitorMut`.
+                #vis trait #visitor_mut_name {
+                    /// Visits a single field.
+                    fn field<T: ?Sized + 'static + #bound>(&mut self, field: #field_enum_name, value: &mut T);
+                }
+            },
+            quote!(#visitor_name),
+            quote!(#visitor_mut_name),
+        )
+    } else {
+        (
+            quote! {},
+            quote!(::enum_companion::FieldVisitor<#field_enum_name>),
+            quote!(::enum_companion::FieldVisitorMut<#field_enum_name>),
+        )
+    };
+
+    // Type-erased and dynamic access is only possible when every field type can be `'static`.
+    let any_impl = if let Some(static_generics) = static_generics(&generics) {
+        let (impl_generics, ty_generics, where_clause) = static_generics.split_for_impl();
+
+        let field_any_arms =
+            field_access
+                .iter()
+                .zip(field_variants.iter())
+                .map(|(access, variant)| match access {
+                    FieldAccess::Stored(ident) | FieldAccess::Opaque(ident) => {
+                        quote! { #field_enum_name::#variant => Some(&self.#ident) }
+                    }
+                    FieldAccess::Mapped { .. } | FieldAccess::Virtual { .. } => {
+                        quote! { #field_enum_name::#variant => None }
+                    }
+                });
+
+        let field_any_mut_arms =
+            field_access
+                .iter()
+                .zip(field_variants.iter())
+                .map(|(access, variant)| match access {
+                    FieldAccess::Stored(ident) | FieldAccess::Opaque(ident) => {
+                        quote! { #field_enum_name::#variant => Some(&mut self.#ident) }
+                    }
+                    FieldAccess::Mapped { .. } | FieldAccess::Virtual { .. } => {
+                        quote! { #field_enum_name::#variant => None }
+                    }
+                });
+
+        let set_any_arms = field_access
+            .iter()
+            .zip(field_variants.iter())
+            .zip(field_types.iter())
+            .map(|((access, variant), ty)| {
+                if access.is_read_only() {
+                    return quote! { #field_enum_name::#variant => Err(value) };
+                }
+                let write = access.write();
+                quote! {
+                    #field_enum_name::#variant => {
+                        let value = *value.downcast::<#ty>()?;
+                        #write;
+                        Ok(())
+                    }
+                }
+            });
+
+        let get_str_arms = value_variants
+            .iter()
+            .map(|variant| {
+                if sensitive_variants.contains(variant) {
+                    quote! { #value_enum_name::#variant(_) => Some("***".to_string()) }
+                } else {
+                    quote! { #value_enum_name::#variant(value) => ::enum_companion::display_any(&value) }
+                }
+            })
+            .chain(empty_value_arm.clone());
+
+        quote! {
+            impl #impl_generics ::enum_companion::EnumCompanionAny<#field_enum_name> for #struct_name #ty_generics #where_clause {
+                fn field_any(&self, field: #field_enum_name) -> Option<&dyn ::std::any::Any> {
+                    match field {
+                        #(#field_any_arms),*
+                    }
+                }
+
+                fn field_any_mut(&mut self, field: #field_enum_name) -> Option<&mut dyn ::std::any::Any> {
+                    match field {
+                        #(#field_any_mut_arms),*
+                    }
+                }
+
+                fn set_any(
+                    &mut self,
+                    field: #field_enum_name,
+                    value: ::std::boxed::Box<dyn ::std::any::Any>,
+                ) -> Result<(), ::std::boxed::Box<dyn ::std::any::Any>> {
+                    match field {
+                        #(#set_any_arms),*
+                    }
+                }
+            }
+
+            impl #impl_generics ::enum_companion::DynCompanion for #struct_name #ty_generics #where_clause {
+                fn struct_name(&self) -> &'static str {
+                    #struct_name_str
+                }
+
+                fn field_names(&self) -> &'static [&'static str] {
+                    &[#(#field_names),*]
+                }
+
+                fn field_meta(&self, index: usize) -> Option<&'static dyn ::enum_companion::EnumCompanionField> {
+                    #field_enum_name::FIELDS
+                        .get(index)
+                        .map(|field| field as &'static dyn ::enum_companion::EnumCompanionField)
+                }
+
+                fn get_any(&self, name: &str) -> Option<&dyn ::std::any::Any> {
+                    let field = #field_enum_name::from_name(name)?;
+                    ::enum_companion::EnumCompanionAny::field_any(self, field)
+                }
+
+                fn get_str(&self, name: &str) -> Option<String> {
+                    let field = #field_enum_name::from_name(name)?;
+                    match self.try_value(field)? {
+                        #(#get_str_arms),*
+                    }
+                }
+
+                fn set_str(&mut self, name: &str, value: &str) -> Result<(), String> {
+                    let field = #field_enum_name::from_name(name)
+                        .ok_or_else(|| format!("Invalid field name: {}", name))?;
+                    if ::enum_companion::EnumCompanionField::is_read_only(&field) {
+                        return Err(format!("Field {} is read-only", name));
+                    }
+                    let type_info = ::enum_companion::EnumCompanionField::type_info(&field);
+                    let value = ::enum_companion::parse_any(value, type_info)
+                        .map_err(|err| format!("Invalid value for field {}: {}", name, err))?;
+                    ::enum_companion::EnumCompanionAny::set_any(self, field, value)
+                        .map_err(|_| format!("Invalid value for field {}", name))
                 }
             }
         }
@@ -345,24 +1637,15 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
     };
 
     let mut unique_types = std::collections::HashMap::new();
-    for (ty, variant) in field_types.iter().zip(field_variants.iter()) {
+    for (ty, variant) in value_types.iter().zip(value_variants.iter()) {
         let key = quote!(#ty).to_string();
         unique_types
             .entry(key)
-            .or_insert_with(|| (ty.clone(), Vec::new()))
+            .or_insert_with(|| ((*ty).clone(), Vec::new()))
             .1
-            .push(variant.clone());
+            .push((*variant).clone());
     }
 
-    let generic_param_idents: std::collections::HashSet<String> = generics
-        .params
-        .iter()
-        .filter_map(|p| match p {
-            syn::GenericParam::Type(ty) => Some(ty.ident.to_string()),
-            _ => None,
-        })
-        .collect();
-
     let try_from_impls = unique_types.values().filter_map(|(ty, variants)| {
         if type_contains_generic(ty, &generic_param_idents) {
             return None;
@@ -410,18 +1693,272 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
         })
     });
 
+    // The binary codec. The `for<'__companion>` binders defer the `Encode` and `Decode` bounds,
+    // as for `take`, so that only structs whose field types support them can be encoded.
+    // A derived `Debug` would print sensitive values, so it is replaced by one printing `***`
+    // for them.
+    let redact_debug = !sensitive_variants.is_empty()
+        && derive_value.iter().any(|path| {
+            path.segments
+                .last()
+                .is_some_and(|segment| segment.ident == "Debug")
+        });
+    let derive_value: Vec<&syn::Path> = derive_value
+        .iter()
+        .filter(|path| {
+            !redact_debug
+                || path
+                    .segments
+                    .last()
+                    .is_none_or(|segment| segment.ident != "Debug")
+        })
+        .collect();
+    let value_debug_impl = if redact_debug {
+        let mut debug_generics = generics.clone();
+        let predicates = &mut debug_generics.make_where_clause().predicates;
+        for (variant, ty) in value_variants.iter().zip(value_types.iter()) {
+            if !sensitive_variants.contains(variant) {
+                predicates.push(syn::parse_quote!(#ty: ::std::fmt::Debug));
+            }
+        }
+        let (_, _, debug_where_clause) = debug_generics.split_for_impl();
+        let debug_arms = value_variants.iter().map(|variant| {
+            let name = variant.to_string();
+            if sensitive_variants.contains(variant) {
+                quote! { Self::#variant(_) => f.debug_tuple(#name).field(&format_args!("***")).finish() }
+            } else {
+                quote! { Self::#variant(value) => f.debug_tuple(#name).field(value).finish() }
+            }
+        }).chain(empty_ref_arm.clone());
+        quote! {
+            impl #impl_generics ::std::fmt::Debug for #value_enum_name #ty_generics #debug_where_clause {
+                fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                    match self {
+                        #(#debug_arms),*
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let codec_impl = {
+        let encode_bounds: Vec<_> = value_types
+            .iter()
+            .map(|ty| quote! { for<'__companion> #ty: ::enum_companion::Encode })
+            .collect();
+        let decode_bounds: Vec<_> = value_types
+            .iter()
+            .map(|ty| quote! { for<'__companion> #ty: ::enum_companion::Decode })
+            .collect();
+
+        // With the empty variant only, `out` is never written and every id is unknown.
+        let empty_encode_allow = (value_count == 0).then(|| quote! { #[allow(clippy::ptr_arg)] });
+        let empty_decode_allow = (value_count == 0).then(|| quote! { #[allow(unreachable_code)] });
+        let encode_arms = value_variants
+            .iter()
+            .zip(value_ids.iter())
+            .map(|(variant, id)| {
+                quote! { Self::#variant(value) => ::enum_companion::codec::write_field(out, #id, value) }
+            })
+            .chain(empty_ref_arm.clone());
+        let decode_arms = value_variants
+            .iter()
+            .zip(value_ids.iter())
+            .map(|(variant, id)| {
+                quote! { #id => Self::#variant(::enum_companion::codec::decode_payload(payload)?) }
+            });
+
+        // Virtual fields are computed from the stored ones, so structs only encode the
+        // stored fields.
+        let (struct_encode_statements, struct_types): (Vec<_>, Vec<_>) = value_fields
+            .iter()
+            .zip(value_ids.iter())
+            .filter_map(|((access, _, _, ty), id)| {
+                let value = match access {
+                    FieldAccess::Stored(ident)
+                    | FieldAccess::Mapped {
+                        ident,
+                        get_with: None,
+                        ..
+                    } => quote! { &self.#ident },
+                    FieldAccess::Mapped { .. } => {
+                        let read = access.read();
+                        quote! { &#read }
+                    }
+                    FieldAccess::Opaque(_) | FieldAccess::Virtual { .. } => return None,
+                };
+                Some((
+                    quote! { ::enum_companion::codec::write_field(out, #id, #value); },
+                    *ty,
+                ))
+            })
+            .unzip();
+        let struct_count = struct_types.len() as u64;
+
+        let mut encode_generics = generics.clone();
+        encode_generics.make_where_clause().predicates.extend(
+            encode_bounds
+                .iter()
+                .map(|bound| -> syn::WherePredicate { syn::parse_quote!(#bound) }),
+        );
+        let (encode_impl_generics, _, encode_where_clause) = encode_generics.split_for_impl();
+
+        let mut decode_generics = generics.clone();
+        decode_generics.make_where_clause().predicates.extend(
+            decode_bounds
+                .iter()
+                .map(|bound| -> syn::WherePredicate { syn::parse_quote!(#bound) }),
+        );
+        let (decode_impl_generics, _, decode_where_clause) = decode_generics.split_for_impl();
+
+        let mut struct_encode_generics = generics.clone();
+        struct_encode_generics
+            .make_where_clause()
+            .predicates
+            .extend(struct_types.iter().map(|ty| -> syn::WherePredicate {
+                syn::parse_quote!(for<'__companion> #ty: ::enum_companion::Encode)
+            }));
+        let (struct_encode_impl_generics, _, struct_encode_where_clause) =
+            struct_encode_generics.split_for_impl();
+
+        let mut struct_decode_generics = decode_generics.clone();
+        struct_decode_generics.make_where_clause().predicates.push(
+            syn::parse_quote!(for<'__companion> #struct_name #ty_generics: ::std::default::Default),
+        );
+        let (struct_decode_impl_generics, _, struct_decode_where_clause) =
+            struct_decode_generics.split_for_impl();
+
+        quote! {
+            impl #impl_generics #value_enum_name #ty_generics #where_clause {
+                /// Appends the value to `out` in the compact binary format: a varint field id,
+                /// then the length-prefixed payload.
+                #empty_encode_allow
+                pub fn encode(&self, out: &mut Vec<u8>)
+                where
+                    #(#encode_bounds),*
+                {
+                    match self {
+                        #(#encode_arms),*
+                    }
+                }
+
+                /// Decodes a value written by `encode` from the start of `input`, and returns it
+                /// with the number of bytes read.
+                ///
+                /// Values of unknown fields are reported with
+                /// `DecodeError::UnknownField`, which gives their size so they can be skipped.
+                #empty_decode_allow
+                pub fn decode(input: &[u8]) -> Result<(Self, usize), ::enum_companion::DecodeError>
+                where
+                    #(#decode_bounds),*
+                {
+                    let (id, payload, len) = ::enum_companion::codec::read_field(input)?;
+                    let value = match id {
+                        #(#decode_arms,)*
+                        id => return Err(::enum_companion::DecodeError::UnknownField { id, len }),
+                    };
+                    Ok((value, len))
+                }
+            }
+
+            impl #encode_impl_generics ::enum_companion::Encode for #value_enum_name #ty_generics #encode_where_clause {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    Self::encode(self, out)
+                }
+            }
+
+            impl #decode_impl_generics ::enum_companion::Decode for #value_enum_name #ty_generics #decode_where_clause {
+                fn decode(input: &[u8]) -> Result<(Self, usize), ::enum_companion::DecodeError> {
+                    Self::decode(input)
+                }
+            }
+
+            /// Structs are written as their number of stored fields, then the value of each.
+            impl #struct_encode_impl_generics ::enum_companion::Encode for #struct_name #ty_generics #struct_encode_where_clause {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    ::enum_companion::codec::write_varint(out, #struct_count);
+                    #(#struct_encode_statements)*
+                }
+            }
+
+            /// Fields missing from the input keep their default value, and unknown fields are
+            /// skipped.
+            impl #struct_decode_impl_generics ::enum_companion::Decode for #struct_name #ty_generics #struct_decode_where_clause {
+                fn decode(input: &[u8]) -> Result<(Self, usize), ::enum_companion::DecodeError> {
+                    let (count, mut read) = ::enum_companion::codec::read_varint(input)?;
+                    let mut decoded = <Self as ::std::default::Default>::default();
+                    for _ in 0..count {
+                        match #value_enum_name::decode(&input[read..]) {
+                            Ok((value, len)) => {
+                                decoded.#update_fn_name(value);
+                                read += len;
+                            }
+                            Err(::enum_companion::DecodeError::UnknownField { len, .. }) => read += len,
+                            Err(err) => return Err(err),
+                        }
+                    }
+                    Ok((decoded, read))
+                }
+            }
+        }
+    };
+
+    let field_ids_lit = field_ids
+        .iter()
+        .map(|id| proc_macro2::Literal::u64_unsuffixed(*id));
+
     // Generate the final token stream.
     let expanded = quote! {
         /// An enum representing the fields of the struct.
         #[allow(dead_code)]
         #[derive(Copy, Clone, #(#derive_field),*)]
         #serde_field_attr
+        #field_repr_attr
         #vis enum #field_enum_name {
             #(#field_enum_variants),*
         }
 
         impl #field_enum_name {
             pub const FIELDS: &'static [#field_enum_name] = &[#(#field_enum_name::#field_variants),*];
+            pub const COUNT: usize = #field_variants_count;
+            /// The fields marked with `#[companion(deprecated = "...")]`.
+            pub const DEPRECATED: &'static [#field_enum_name] = &[#(#field_enum_name::#deprecated_variants),*];
+            /// The fields which aren't deprecated.
+            pub const ACTIVE: &'static [#field_enum_name] = &[#(#field_enum_name::#active_variants),*];
+
+            /// Returns the id of the field, given with `#[companion(id = ...)]`, or its position.
+            pub const fn id(self) -> #id_ty {
+                self as #id_ty
+            }
+
+            /// Returns the field with the given id.
+            pub fn from_id(id: #id_ty) -> Option<Self> {
+                match id {
+                    #(#field_ids_lit => Some(Self::#field_variants),)*
+                    _ => None,
+                }
+            }
+
+            /// Returns the field with the given name, variant name or old name.
+            ///
+            /// Unlike `FromStr`, this records no diagnostic for deprecated fields and calls no
+            /// `on_old_name` hook, so it suits lookups which don't come from user input.
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    #(#from_name_arms,)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl ::std::convert::TryFrom<#id_ty> for #field_enum_name {
+            type Error = String;
+
+            fn try_from(id: #id_ty) -> Result<Self, Self::Error> {
+                Self::from_id(id).ok_or_else(|| format!("Invalid field id: {}", id))
+            }
         }
 
         #enum_companion_field_impl
@@ -434,8 +1971,16 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
             #(#value_enum_variants),*
         }
 
+        #value_debug_impl
+
         #enum_companion_value_impl
 
+        #codec_impl
+
+        #value_ref_impl
+
+        #visitor_traits
+
         impl std::str::FromStr for #field_enum_name {
             type Err = String;
 
@@ -453,31 +1998,182 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
                 #field_enum_name::FIELDS
             }
 
+            /// Returns the field enum variants, leaving out deprecated fields.
+            pub fn fields_active() -> &'static [#field_enum_name] {
+                #field_enum_name::ACTIVE
+            }
+
             /// Returns a vector of all field values.
             pub fn as_values(&self) -> Vec<#value_enum_name #ty_generics> {
-                Self::#fields_fn_name()
+                self.iter_values().collect()
+            }
+
+            /// Returns a vector of all field values, leaving out sensitive fields.
+            pub fn as_values_redacted(&self) -> Vec<#value_enum_name #ty_generics> {
+                #field_enum_name::FIELDS
                     .iter()
-                    .map(|&field| self.#value_fn_name(field))
+                    .filter(|field| !::enum_companion::EnumCompanionField::is_sensitive(*field))
+                    .filter_map(|&field| self.try_value(field))
                     .collect()
             }
 
+            /// Returns an array of all field values, without allocating. Opaque fields are left out.
+            pub fn values_array(&self) -> [#value_enum_name #ty_generics; #value_count] {
+                [#(#values_array_values),*]
+            }
+
+            /// Returns a lazy iterator over all field values. Opaque fields are left out.
+            pub fn iter_values(&self) -> impl Iterator<Item = #value_enum_name #ty_generics> + '_ {
+                #field_enum_name::FIELDS
+                    .iter()
+                    .filter_map(move |&field| self.try_value(field))
+            }
+
+            /// Returns a lazy iterator over references to the values of all stored fields
+            /// without `get_with` or `set_with`, without cloning.
+            pub fn iter_value_refs(&self) -> impl Iterator<Item = #value_ref_enum_name<'_, #ty_params>> + '_ {
+                #field_enum_name::FIELDS
+                    .iter()
+                    .filter_map(move |&field| self.value_ref(field))
+            }
+
+            /// Returns a reference to the value of a specific field, or `None` for a virtual field
+            /// or a field with `get_with` or `set_with`.
+            pub fn value_ref(&self, field: #field_enum_name) -> Option<#value_ref_enum_name<'_, #ty_params>> {
+                match field {
+                    #(#value_ref_match_arms),*
+                }
+            }
+
             /// Returns the value of a specific field.
+            ///
+            /// Panics if the field is opaque, see `try_value`.
             pub fn #value_fn_name(&self, field: #field_enum_name) -> #value_enum_name #ty_generics {
                 match field {
                     #(#value_match_arms),*
                 }
             }
 
+            /// Returns the value of a specific field, or `None` if the field is opaque.
+            pub fn try_value(&self, field: #field_enum_name) -> Option<#value_enum_name #ty_generics> {
+                (!::enum_companion::EnumCompanionField::is_opaque(&field))
+                    .then(|| self.#value_fn_name(field))
+            }
+
             /// Updates the value of a specific field.
             pub fn #update_fn_name(&mut self, value: #value_enum_name #ty_generics) {
                 match value {
                     #(#update_match_arms),*
                 }
             }
+
+            /// Returns the fields whose value `role` may read. Opaque fields, which have no
+            /// value, are left out.
+            pub fn fields_readable_by(role: &(impl ::enum_companion::Role + ?Sized)) -> Vec<#field_enum_name> {
+                #field_enum_name::FIELDS
+                    .iter()
+                    .copied()
+                    .filter(|field| {
+                        !::enum_companion::EnumCompanionField::is_opaque(field)
+                            && role.can(::enum_companion::EnumCompanionField::read_access(field))
+                    })
+                    .collect()
+            }
+
+            /// Returns the value of a field, or an error if `role` may not read it or the field
+            /// is opaque.
+            pub fn value_as(
+                &self,
+                role: &(impl ::enum_companion::Role + ?Sized),
+                field: #field_enum_name,
+            ) -> Result<#value_enum_name #ty_generics, ::enum_companion::AccessError> {
+                let name = ::enum_companion::EnumCompanionField::name(&field);
+                if !role.can(::enum_companion::EnumCompanionField::read_access(&field)) {
+                    return Err(::enum_companion::AccessError::Read(name));
+                }
+                self.try_value(field)
+                    .ok_or(::enum_companion::AccessError::Opaque(name))
+            }
+
+            /// Updates the value of a field, or returns an error if `role` may not write it.
+            pub fn update_as(
+                &mut self,
+                role: &(impl ::enum_companion::Role + ?Sized),
+                value: #value_enum_name #ty_generics,
+            ) -> Result<(), ::enum_companion::AccessError> {
+                let field = ::enum_companion::EnumCompanionValue::field(&value);
+                if !role.can(::enum_companion::EnumCompanionField::write_access(&field)) {
+                    let name = ::enum_companion::EnumCompanionField::name(&field);
+                    return Err(::enum_companion::AccessError::Write(name));
+                }
+                self.#update_fn_name(value);
+                Ok(())
+            }
+
+            /// Starts a transaction on the struct, see `enum_companion::Transaction`.
+            pub fn begin(&mut self) -> ::enum_companion::Transaction<'_, Self> {
+                ::enum_companion::Transaction::new(self)
+            }
+
+            /// Updates several fields at once. If any value is rejected, none is applied.
+            pub fn update_many(
+                &mut self,
+                values: impl IntoIterator<Item = #value_enum_name #ty_generics>,
+            ) -> Result<(), ::enum_companion::BatchError> {
+                let mut transaction = self.begin();
+                for value in values {
+                    transaction.set(value)?;
+                }
+                transaction.commit()
+            }
+
+            /// Passes a reference to each field, with its concrete type, to the visitor.
+            pub fn visit<V: #visitor_path>(&self, visitor: &mut V)
+            where
+                #(#field_types: 'static),*
+            {
+                #(#visit_statements)*
+            }
+
+            /// Passes a mutable reference to each stored field, with its concrete type, to the
+            /// visitor. Virtual fields and fields with `get_with` or `set_with` are not visited.
+            pub fn visit_mut<V: #visitor_mut_path>(&mut self, visitor: &mut V)
+            where
+                #(#visit_mut_types: 'static),*
+            {
+                #(#visit_mut_statements)*
+            }
+
+            /// Consumes the struct and returns all field values as an array, without cloning.
+            /// Opaque fields are dropped.
+            pub fn into_parts(self) -> [#value_enum_name #ty_generics; #value_count] {
+                #(#into_parts_statements)*
+                [#(#into_parts_values),*]
+            }
+
+            /// Consumes the struct and returns a vector of all field values, without cloning.
+            pub fn into_values(self) -> Vec<#value_enum_name #ty_generics> {
+                Vec::from(self.into_parts())
+            }
+
+            /// Takes the value of a specific field, leaving `Default::default()` in its place.
+            ///
+            /// Only available when every field type implements `Default`. Panics if the field
+            /// is opaque.
+            pub fn take(&mut self, field: #field_enum_name) -> #value_enum_name #ty_generics
+            where
+                #(#take_bounds),*
+            {
+                match field {
+                    #(#take_match_arms),*
+                }
+            }
         }
 
         #trait_impl
 
+        #any_impl
+
         #(#try_from_impls)*
 
         #(#try_into_impls)*
@@ -486,6 +2182,44 @@ pub fn enum_companion_derive(input: TokenStream) -> TokenStream {
     TokenStream::from(expanded)
 }
 
+/// Renames `virtual(...)` options in `#[companion(...)]` struct attributes to `virtual_field(...)`.
+///
+/// `virtual` is a reserved keyword, which `syn` doesn't accept as the name of a nested option.
+fn rename_keyword_options(attrs: &mut [syn::Attribute]) {
+    for attr in attrs {
+        if let syn::Meta::List(list) = &mut attr.meta
+            && list.path.is_ident("companion")
+        {
+            list.tokens = std::mem::take(&mut list.tokens)
+                .into_iter()
+                .map(|token| match token {
+                    proc_macro2::TokenTree::Ident(ident) if ident == "virtual" => {
+                        proc_macro2::TokenTree::Ident(Ident::new("virtual_field", ident.span()))
+                    }
+                    token => token,
+                })
+                .collect();
+        }
+    }
+}
+
+/// Returns a copy of `generics` where every type parameter is bounded by `'static`,
+/// or `None` if the struct has lifetime parameters and therefore cannot be `'static`.
+fn static_generics(generics: &syn::Generics) -> Option<syn::Generics> {
+    if generics.lifetimes().next().is_some() {
+        return None;
+    }
+    let mut generics = generics.clone();
+    let type_params: Vec<Ident> = generics.type_params().map(|p| p.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    for ident in type_params {
+        where_clause
+            .predicates
+            .push(syn::parse_quote!(#ident: 'static));
+    }
+    Some(generics)
+}
+
 /// Converts a string to PascalCase.
 fn to_pascal_case(s: &str) -> String {
     s.split('_')
@@ -509,13 +2243,12 @@ impl<'ast, 'a> Visit<'ast> for GenericVisitor<'a> {
         if self.contains_generic {
             return;
         }
-        if i.qself.is_none() {
-            if let Some(segment) = i.path.segments.last() {
-                if self.generic_params.contains(&segment.ident.to_string()) {
-                    self.contains_generic = true;
-                    return;
-                }
-            }
+        if i.qself.is_none()
+            && let Some(segment) = i.path.segments.last()
+            && self.generic_params.contains(&segment.ident.to_string())
+        {
+            self.contains_generic = true;
+            return;
         }
         visit::visit_type_path(self, i);
     }
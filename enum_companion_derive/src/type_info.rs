@@ -0,0 +1,136 @@
+//! Derive-time analysis of field types into `enum_companion::TypeInfo` expressions.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use std::collections::HashSet;
+use syn::{Expr, GenericArgument, PathArguments, Type};
+
+/// Builds a constant `::enum_companion::TypeInfo` expression describing `ty`.
+///
+/// `type_params` and `const_params` are the names of the struct's generic parameters.
+pub(crate) fn type_info_expr(
+    ty: &Type,
+    type_params: &HashSet<String>,
+    const_params: &HashSet<String>,
+) -> TokenStream {
+    let opaque = || {
+        let type_str = quote!(#ty).to_string();
+        quote! { ::enum_companion::TypeInfo::Opaque(#type_str) }
+    };
+    let nested = |ty: &Type| {
+        let inner = type_info_expr(ty, type_params, const_params);
+        quote! { &#inner }
+    };
+
+    match ty {
+        Type::Paren(paren) => type_info_expr(&paren.elem, type_params, const_params),
+        Type::Group(group) => type_info_expr(&group.elem, type_params, const_params),
+        Type::Reference(reference) => {
+            let mutable = reference.mutability.is_some();
+            let inner = nested(&reference.elem);
+            quote! { ::enum_companion::TypeInfo::Reference { mutable: #mutable, inner: #inner } }
+        }
+        Type::Tuple(tuple) => {
+            let elems = tuple
+                .elems
+                .iter()
+                .map(|elem| type_info_expr(elem, type_params, const_params));
+            quote! { ::enum_companion::TypeInfo::Tuple(&[#(#elems),*]) }
+        }
+        Type::Slice(slice) => sequence(quote!(Slice), nested(&slice.elem)),
+        Type::Array(array) => match &array.len {
+            Expr::Path(path)
+                if path
+                    .path
+                    .get_ident()
+                    .is_some_and(|ident| const_params.contains(&ident.to_string())) =>
+            {
+                opaque()
+            }
+            len => sequence(quote!(Array(#len)), nested(&array.elem)),
+        },
+        Type::Path(path) if path.qself.is_none() => {
+            let Some(segment) = path.path.segments.last() else {
+                return opaque();
+            };
+            let name = segment.ident.to_string();
+            let args: Vec<&Type> = match &segment.arguments {
+                PathArguments::AngleBracketed(args) => args
+                    .args
+                    .iter()
+                    .filter_map(|arg| match arg {
+                        GenericArgument::Type(ty) => Some(ty),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+
+            if path.path.segments.len() == 1 && args.is_empty() {
+                if type_params.contains(&name) {
+                    return quote! { ::enum_companion::TypeInfo::Generic(#name) };
+                }
+                if let Some(primitive) = primitive(&name) {
+                    return quote! {
+                        ::enum_companion::TypeInfo::Primitive(::enum_companion::Primitive::#primitive)
+                    };
+                }
+            }
+
+            match (name.as_str(), args.as_slice()) {
+                ("String", []) => quote! {
+                    ::enum_companion::TypeInfo::Primitive(::enum_companion::Primitive::Str)
+                },
+                ("Option", [inner]) => {
+                    let inner = nested(inner);
+                    quote! { ::enum_companion::TypeInfo::Option(#inner) }
+                }
+                ("Vec", [element]) => sequence(quote!(Vec), nested(element)),
+                ("VecDeque", [element]) => sequence(quote!(Deque), nested(element)),
+                ("HashSet" | "BTreeSet", [element, ..]) => sequence(quote!(Set), nested(element)),
+                ("HashMap" | "BTreeMap", [key, value, ..]) => {
+                    let key = nested(key);
+                    let value = nested(value);
+                    quote! { ::enum_companion::TypeInfo::Map { key: #key, value: #value } }
+                }
+                _ => opaque(),
+            }
+        }
+        _ => opaque(),
+    }
+}
+
+/// Builds a `TypeInfo::Sequence` expression.
+fn sequence(kind: TokenStream, element: TokenStream) -> TokenStream {
+    quote! {
+        ::enum_companion::TypeInfo::Sequence {
+            kind: ::enum_companion::SequenceKind::#kind,
+            element: #element,
+        }
+    }
+}
+
+/// Maps a primitive type name to its `enum_companion::Primitive` variant.
+fn primitive(name: &str) -> Option<TokenStream> {
+    let variant = match name {
+        "bool" => quote!(Bool),
+        "char" => quote!(Char),
+        "str" => quote!(Str),
+        "i8" => quote!(I8),
+        "i16" => quote!(I16),
+        "i32" => quote!(I32),
+        "i64" => quote!(I64),
+        "i128" => quote!(I128),
+        "isize" => quote!(Isize),
+        "u8" => quote!(U8),
+        "u16" => quote!(U16),
+        "u32" => quote!(U32),
+        "u64" => quote!(U64),
+        "u128" => quote!(U128),
+        "usize" => quote!(Usize),
+        "f32" => quote!(F32),
+        "f64" => quote!(F64),
+        _ => return None,
+    };
+    Some(variant)
+}